@@ -0,0 +1,498 @@
+use crate::state::bitboard::{
+    bishop_attacks, kind_index, knight_attacks, queen_attacks, rook_attacks, squares, Bitboard,
+    FILE_A, LIGHT_SQUARES,
+};
+use crate::state::board::PieceKind;
+use crate::state::game::{GameState, PlayerColor};
+use std::fmt::{Display, Formatter};
+
+/// The weights shipped with the game, also used for anything a custom file leaves out.
+const DEFAULT_WEIGHTS: &str = include_str!("../../assets/eval.conf");
+
+/// How much each piece counts towards the middlegame, a full board adds up to [MAX_PHASE].
+const PHASE: [i32; 6] = [0, 2, 1, 1, 0, 4];
+const MAX_PHASE: i32 = 24;
+
+/// Scores a position in centipawns from the point of view of the side to move.
+pub trait Evaluator {
+    fn evaluate(&self, state: &GameState) -> i32;
+}
+
+/// A middlegame and an endgame value, blended by how much material is left.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Weight {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl std::ops::AddAssign for Weight {
+    fn add_assign(&mut self, other: Weight) {
+        self.mg += other.mg;
+        self.eg += other.eg;
+    }
+}
+
+impl std::ops::Mul<i32> for Weight {
+    type Output = Weight;
+
+    fn mul(self, factor: i32) -> Weight {
+        Weight {
+            mg: self.mg * factor,
+            eg: self.eg * factor,
+        }
+    }
+}
+
+/// Everything the tapered evaluation can be tuned with, indexed by [kind_index] where per piece.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Weights {
+    pub material: [Weight; 6],
+    pub psts: [[Weight; 64]; 6],
+    pub doubled_pawn: Weight,
+    pub isolated_pawn: Weight,
+    pub passed_pawn: [Weight; 8],
+    pub bishop_pair: Weight,
+    pub mobility: [Weight; 6],
+    pub king_shield: Weight,
+    pub king_open_file: Weight,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WeightsError {
+    Io(String),
+    UnknownKey(String),
+    InvalidValue(String),
+    WrongValueCount {
+        key: String,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl Display for WeightsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WeightsError::Io(e) => write!(f, "could not read weights: {}", e),
+            WeightsError::UnknownKey(key) => write!(f, "unknown weight '{}'", key),
+            WeightsError::InvalidValue(value) => write!(f, "invalid weight '{}'", value),
+            WeightsError::WrongValueCount {
+                key,
+                expected,
+                found,
+            } => write!(f, "'{}' needs {} values, found {}", key, expected, found),
+        }
+    }
+}
+
+impl std::error::Error for WeightsError {}
+
+fn piece_name(kind: PieceKind) -> &'static str {
+    match kind {
+        PieceKind::Pawn => "pawn",
+        PieceKind::Rook => "rook",
+        PieceKind::Knight => "knight",
+        PieceKind::Bishop => "bishop",
+        PieceKind::King => "king",
+        PieceKind::Queen => "queen",
+    }
+}
+
+const KINDS: [PieceKind; 6] = [
+    PieceKind::Pawn,
+    PieceKind::Rook,
+    PieceKind::Knight,
+    PieceKind::Bishop,
+    PieceKind::King,
+    PieceKind::Queen,
+];
+
+impl Default for Weights {
+    fn default() -> Self {
+        let mut weights = Weights {
+            material: [Weight::default(); 6],
+            psts: [[Weight::default(); 64]; 6],
+            doubled_pawn: Weight::default(),
+            isolated_pawn: Weight::default(),
+            passed_pawn: [Weight::default(); 8],
+            bishop_pair: Weight::default(),
+            mobility: [Weight::default(); 6],
+            king_shield: Weight::default(),
+            king_open_file: Weight::default(),
+        };
+        weights
+            .apply(DEFAULT_WEIGHTS)
+            .expect("The built-in weights are valid!");
+        weights
+    }
+}
+
+impl Weights {
+    /// The built-in weights, overridden by those in the file at `path`.
+    pub fn load(path: &str) -> Result<Weights, WeightsError> {
+        let text = std::fs::read_to_string(path).map_err(|e| WeightsError::Io(e.to_string()))?;
+        let mut weights = Weights::default();
+        weights.apply(&text)?;
+        Ok(weights)
+    }
+
+    /// Overrides the weights named in `text`: keys each followed by their whitespace
+    /// separated values, which may span lines, and `#` comments.
+    pub fn apply(&mut self, text: &str) -> Result<(), WeightsError> {
+        let mut entries: Vec<(&str, Vec<i32>)> = vec![];
+        let tokens = text
+            .lines()
+            .flat_map(|line| line.split('#').next().unwrap_or("").split_whitespace());
+        for token in tokens {
+            if token.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                entries.push((token, vec![]));
+                continue;
+            }
+            let value = token
+                .parse::<i32>()
+                .map_err(|_| WeightsError::InvalidValue(token.to_string()))?;
+            match entries.last_mut() {
+                Some((_, values)) => values.push(value),
+                None => return Err(WeightsError::InvalidValue(token.to_string())),
+            }
+        }
+
+        for (key, values) in entries {
+            self.set(key, &values)?;
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, values: &[i32]) -> Result<(), WeightsError> {
+        let count = |expected: usize| {
+            if values.len() == expected {
+                Ok(())
+            } else {
+                Err(WeightsError::WrongValueCount {
+                    key: key.to_string(),
+                    expected,
+                    found: values.len(),
+                })
+            }
+        };
+        let pair = || {
+            count(2).map(|_| Weight {
+                mg: values[0],
+                eg: values[1],
+            })
+        };
+
+        match key {
+            "doubled_pawn" => self.doubled_pawn = pair()?,
+            "isolated_pawn" => self.isolated_pawn = pair()?,
+            "bishop_pair" => self.bishop_pair = pair()?,
+            "king_shield" => self.king_shield = pair()?,
+            "king_open_file" => self.king_open_file = pair()?,
+            "passed_pawn_mg" | "passed_pawn_eg" => {
+                count(8)?;
+                for (weight, value) in self.passed_pawn.iter_mut().zip(values) {
+                    match key {
+                        "passed_pawn_mg" => weight.mg = *value,
+                        _ => weight.eg = *value,
+                    }
+                }
+            }
+            _ => {
+                let unknown = || WeightsError::UnknownKey(key.to_string());
+                let (table, rest) = key.split_once('_').ok_or_else(unknown)?;
+                let (name, phase) = match rest.split_once('_') {
+                    Some((name, phase)) => (name, Some(phase)),
+                    None => (rest, None),
+                };
+                let kind = KINDS
+                    .into_iter()
+                    .find(|kind| piece_name(*kind) == name)
+                    .ok_or_else(unknown)?;
+                let index = kind_index(kind);
+
+                match (table, phase) {
+                    ("material", None) => self.material[index] = pair()?,
+                    ("mobility", None) => self.mobility[index] = pair()?,
+                    ("pst", Some(phase @ ("mg" | "eg"))) => {
+                        count(64)?;
+                        for (weight, value) in self.psts[index].iter_mut().zip(values) {
+                            match phase {
+                                "mg" => weight.mg = *value,
+                                _ => weight.eg = *value,
+                            }
+                        }
+                    }
+                    _ => return Err(unknown()),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Material, piece-square tables, pawn structure, king safety, mobility and the bishop pair,
+/// each weighted between middlegame and endgame by the material left on the board.
+#[derive(Clone, Debug, Default)]
+pub struct TaperedEval {
+    weights: Weights,
+}
+
+impl TaperedEval {
+    pub fn new(weights: Weights) -> Self {
+        Self { weights }
+    }
+
+    /// The score of `color` alone, to be compared with that of the opponent.
+    fn side(&self, state: &GameState, color: PlayerColor) -> Weight {
+        let weights = &self.weights;
+        let board = state.board();
+        let own = board.color(color);
+        let occupied = board.occupied();
+        let mut score = Weight::default();
+
+        for kind in KINDS {
+            let index = kind_index(kind);
+            for square in squares(board.pieces(kind, color)) {
+                // the tables are written for white, black sees them upside down
+                let relative = match color {
+                    PlayerColor::White => square,
+                    PlayerColor::Black => square ^ 56,
+                };
+                score += weights.material[index];
+                score += weights.psts[index][relative];
+
+                let attacks = match kind {
+                    PieceKind::Knight => knight_attacks(square),
+                    PieceKind::Bishop => bishop_attacks(square, occupied),
+                    PieceKind::Rook => rook_attacks(square, occupied),
+                    PieceKind::Queen => queen_attacks(square, occupied),
+                    PieceKind::Pawn | PieceKind::King => 0,
+                };
+                score += weights.mobility[index] * (attacks & !own).count_ones() as i32;
+            }
+        }
+
+        let bishops = board.pieces(PieceKind::Bishop, color);
+        if bishops & LIGHT_SQUARES != 0 && bishops & !LIGHT_SQUARES != 0 {
+            score += weights.bishop_pair;
+        }
+
+        score += self.pawn_structure(state, color);
+        score += self.king_safety(state, color);
+        score
+    }
+
+    fn pawn_structure(&self, state: &GameState, color: PlayerColor) -> Weight {
+        let weights = &self.weights;
+        let board = state.board();
+        let pawns = board.pieces(PieceKind::Pawn, color);
+        let enemy_pawns = board.pieces(PieceKind::Pawn, color.opponent());
+        let mut score = Weight::default();
+
+        for file in 0..8 {
+            let on_file = (pawns & (FILE_A << file)).count_ones() as i32;
+            if on_file > 1 {
+                score += weights.doubled_pawn * (on_file - 1);
+            }
+        }
+
+        for square in squares(pawns) {
+            let (x, y) = ((square % 8) as i32, (square / 8) as i32);
+            let files = neighbour_files(x);
+            if pawns & (files & !(FILE_A << x)) == 0 {
+                score += weights.isolated_pawn;
+            }
+
+            if enemy_pawns & files & ranks_ahead(color, y) == 0 {
+                let rank = match color {
+                    PlayerColor::White => 7 - y,
+                    PlayerColor::Black => y,
+                };
+                score += weights.passed_pawn[rank as usize];
+            }
+        }
+
+        score
+    }
+
+    fn king_safety(&self, state: &GameState, color: PlayerColor) -> Weight {
+        let weights = &self.weights;
+        let board = state.board();
+        let pawns = board.pieces(PieceKind::Pawn, color);
+        let king = state.king_position(color);
+        let mut score = Weight::default();
+
+        let ahead = match color {
+            PlayerColor::White => (king.1 - 2..king.1).fold(0, |ranks, y| ranks | rank(y)),
+            PlayerColor::Black => (king.1 + 1..=king.1 + 2).fold(0, |ranks, y| ranks | rank(y)),
+        };
+        let shield = pawns & neighbour_files(king.0) & ahead;
+        score += weights.king_shield * shield.count_ones() as i32;
+
+        for x in (king.0 - 1).max(0)..=(king.0 + 1).min(7) {
+            if pawns & (FILE_A << x) == 0 {
+                score += weights.king_open_file;
+            }
+        }
+
+        score
+    }
+}
+
+/// The file `x` and the files next to it.
+fn neighbour_files(x: i32) -> Bitboard {
+    ((x - 1).max(0)..=(x + 1).min(7)).fold(0, |files, x| files | (FILE_A << x))
+}
+
+fn rank(y: i32) -> Bitboard {
+    if (0..8).contains(&y) {
+        0xff << (8 * y)
+    } else {
+        0
+    }
+}
+
+/// Every rank in front of `y` as seen by `color`.
+fn ranks_ahead(color: PlayerColor, y: i32) -> Bitboard {
+    match color {
+        PlayerColor::White => (0..y).fold(0, |ranks, y| ranks | rank(y)),
+        PlayerColor::Black => (y + 1..8).fold(0, |ranks, y| ranks | rank(y)),
+    }
+}
+
+impl Evaluator for TaperedEval {
+    fn evaluate(&self, state: &GameState) -> i32 {
+        let board = state.board();
+        let phase = KINDS
+            .into_iter()
+            .map(|kind| PHASE[kind_index(kind)] * board.kind(kind).count_ones() as i32)
+            .sum::<i32>()
+            .min(MAX_PHASE);
+
+        let mut score = self.side(state, PlayerColor::White);
+        let black = self.side(state, PlayerColor::Black);
+        score.mg -= black.mg;
+        score.eg -= black.eg;
+
+        let blended = (score.mg * phase + score.eg * (MAX_PHASE - phase)) / MAX_PHASE;
+        match state.active_color() {
+            PlayerColor::White => blended,
+            PlayerColor::Black => -blended,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::eval::{Evaluator, TaperedEval, Weight, Weights, WeightsError};
+    use crate::state::game::GameState;
+
+    fn applied(text: &str) -> Result<Weights, WeightsError> {
+        let mut weights = Weights::default();
+        weights.apply(text).map(|_| weights)
+    }
+
+    #[test]
+    fn errors() {
+        let unknown = |key: &str| Err(WeightsError::UnknownKey(key.to_string()));
+        assert_eq!(applied("material_dragon 1 2"), unknown("material_dragon"));
+        assert_eq!(applied("pst_pawn_opening 0"), unknown("pst_pawn_opening"));
+        assert_eq!(applied("tempo 10 10"), unknown("tempo"));
+
+        let invalid = |value: &str| Err(WeightsError::InvalidValue(value.to_string()));
+        assert_eq!(applied("doubled_pawn -10 7z"), invalid("7z"));
+        assert_eq!(applied("doubled_pawn -10 2.5"), invalid("2.5"));
+        // values before the first key belong to nothing
+        assert_eq!(applied("5 6\ndoubled_pawn 1 2"), invalid("5"));
+
+        assert_eq!(
+            applied("bishop_pair 30"),
+            Err(WeightsError::WrongValueCount {
+                key: "bishop_pair".to_string(),
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(
+            applied("pst_knight_eg 1 2 3"),
+            Err(WeightsError::WrongValueCount {
+                key: "pst_knight_eg".to_string(),
+                expected: 64,
+                found: 3
+            })
+        );
+        assert!(matches!(
+            Weights::load("./no/such/eval.conf"),
+            Err(WeightsError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn overrides() {
+        let defaults = Weights::default();
+        assert_eq!(defaults.material[0], Weight { mg: 82, eg: 94 });
+
+        let weights = applied(
+            "material_pawn 100 120 # a comment\n\
+             # a line of comment\n\
+             bishop_pair\n  30\n  60\n\
+             passed_pawn_eg 0 1 2 3 4 5 6 0",
+        )
+        .unwrap();
+        assert_eq!(weights.material[0], Weight { mg: 100, eg: 120 });
+        assert_eq!(weights.bishop_pair, Weight { mg: 30, eg: 60 });
+        assert_eq!(weights.passed_pawn[6], Weight { mg: 60, eg: 6 });
+
+        // everything left out keeps the value of assets/eval.conf
+        let restored = Weights {
+            material: defaults.material,
+            bishop_pair: defaults.bishop_pair,
+            passed_pawn: defaults.passed_pawn,
+            ..weights
+        };
+        assert_eq!(restored, defaults);
+    }
+
+    /// The same position with the colors swapped and the board turned around.
+    fn mirrored(fen: &str) -> String {
+        let fields = fen.split_whitespace().collect::<Vec<_>>();
+        let swap_case = |text: &str| {
+            text.chars()
+                .map(|c| match c.is_ascii_uppercase() {
+                    true => c.to_ascii_lowercase(),
+                    false => c.to_ascii_uppercase(),
+                })
+                .collect::<String>()
+        };
+        let placement = fields[0].split('/').rev().collect::<Vec<_>>().join("/");
+        let active = if fields[1] == "w" { "b" } else { "w" };
+        format!(
+            "{} {} {} {}",
+            swap_case(&placement),
+            active,
+            fields[2..4].join(" "),
+            fields[4..].join(" ")
+        )
+    }
+
+    #[test]
+    fn symmetric_between_colors() {
+        let eval = TaperedEval::default();
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w - - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 0 1",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            "6k1/5ppp/8/8/2B5/8/5PPP/6K1 w - - 0 1",
+        ] {
+            let state = GameState::from_fen(fen).unwrap();
+            let mirror = GameState::from_fen(&mirrored(fen)).unwrap();
+            assert_eq!(eval.evaluate(&state), eval.evaluate(&mirror), "{}", fen);
+        }
+
+        // an extra piece is good for whoever has it
+        let up_a_bishop = GameState::from_fen("6k1/5ppp/8/8/2B5/8/5PPP/6K1 w - - 0 1").unwrap();
+        assert!(eval.evaluate(&up_a_bishop) > 200);
+        let down_a_bishop = GameState::from_fen("6k1/5ppp/8/8/2B5/8/5PPP/6K1 b - - 0 1").unwrap();
+        assert_eq!(eval.evaluate(&down_a_bishop), -eval.evaluate(&up_a_bishop));
+    }
+}
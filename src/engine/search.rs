@@ -0,0 +1,534 @@
+use crate::engine::eval::{Evaluator, TaperedEval};
+use crate::engine::tt::{Bound, TableStats, TranspositionTable};
+use crate::state::board::PieceKind;
+use crate::state::game::{GamePhase, GameState, Move, Position};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The score of delivering mate right now, mates further away score one less per ply.
+pub const MATE: i32 = 30_000;
+const INFINITY: i32 = 32_000;
+pub(super) const MAX_PLY: usize = 128;
+/// How many nodes pass between looking at the clock and the stop flag.
+const CHECK_INTERVAL: u64 = 2048;
+/// Size of the transposition table in megabytes unless configured otherwise.
+pub const DEFAULT_HASH_SIZE: usize = 16;
+
+/// When to stop searching, no limit at all searches until stopped from outside.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub depth: Option<u32>,
+    pub movetime: Option<Duration>,
+    pub nodes: Option<u64>,
+}
+
+/// What a finished iteration found, reported while the search goes on.
+#[derive(Clone, Debug)]
+pub struct SearchInfo {
+    pub depth: u32,
+    /// Centipawns from the point of view of the side to move.
+    pub score: i32,
+    pub nodes: u64,
+    pub time: Duration,
+    pub pv: Vec<(Position, Move)>,
+    /// How full the transposition table is, in permille.
+    pub hashfull: u32,
+}
+
+impl SearchInfo {
+    /// Full moves until mate, negative if the side to move gets mated.
+    pub fn mate_in(&self) -> Option<i32> {
+        if self.score.abs() < MATE - MAX_PLY as i32 {
+            return None;
+        }
+        let plies = MATE - self.score.abs();
+        let moves = (plies + 1) / 2;
+        Some(if self.score > 0 { moves } else { -moves })
+    }
+}
+
+fn piece_value(kind: PieceKind) -> i32 {
+    match kind {
+        PieceKind::Pawn => 100,
+        PieceKind::Knight => 320,
+        PieceKind::Bishop => 330,
+        PieceKind::Rook => 500,
+        PieceKind::Queen => 900,
+        PieceKind::King => 0,
+    }
+}
+
+/// The piece a move takes, if any.
+fn victim(state: &GameState, m: Move) -> Option<PieceKind> {
+    match m {
+        Move::Take(_, victim) => state.piece_at(victim).map(|p| p.kind),
+        Move::Promote(to, _) => state.piece_at(to).map(|p| p.kind),
+        _ => None,
+    }
+}
+
+fn is_tactical(state: &GameState, m: Move) -> bool {
+    matches!(m, Move::Promote(..)) || victim(state, m).is_some()
+}
+
+/// The score of a game that ended as `phase`, for the side to move `ply` plies from the root.
+fn game_end_score(state: &GameState, phase: GamePhase, ply: usize) -> i32 {
+    match phase {
+        GamePhase::Won(winner, _) if winner == state.active_color() => MATE - ply as i32,
+        GamePhase::Won(..) => -MATE + ply as i32,
+        GamePhase::Draw(_) | GamePhase::Turn(_) => 0,
+    }
+}
+
+pub struct Engine {
+    evaluator: Box<dyn Evaluator + Send>,
+    tt: TranspositionTable,
+    killers: Vec<[Option<(Position, Move)>; 2]>,
+    history: Vec<[u32; 64]>,
+    pv: Vec<Vec<(Position, Move)>>,
+    path: Vec<u64>,
+    nodes: u64,
+    node_limit: Option<u64>,
+    deadline: Option<Instant>,
+    stop: Arc<AtomicBool>,
+    aborted: bool,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::with_evaluator(Box::new(TaperedEval::default()))
+    }
+
+    pub fn with_evaluator(evaluator: Box<dyn Evaluator + Send>) -> Self {
+        Self {
+            evaluator,
+            tt: TranspositionTable::new(DEFAULT_HASH_SIZE),
+            killers: vec![[None; 2]; MAX_PLY],
+            history: vec![[0; 64]; 64],
+            pv: vec![vec![]; MAX_PLY + 1],
+            path: vec![],
+            nodes: 0,
+            node_limit: None,
+            deadline: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            aborted: false,
+        }
+    }
+
+    /// Replaces the transposition table with an empty one of `megabytes` size.
+    pub fn set_hash_size(&mut self, megabytes: usize) {
+        self.tt.resize(megabytes);
+    }
+
+    /// Forgets everything learned in earlier searches, e.g. before a new game.
+    pub fn clear(&mut self) {
+        self.tt.clear();
+        self.killers
+            .iter_mut()
+            .for_each(|killers| *killers = [None; 2]);
+        self.history.iter_mut().for_each(|row| *row = [0; 64]);
+    }
+
+    pub fn hash_stats(&self) -> TableStats {
+        self.tt.stats()
+    }
+
+    /// A flag that ends a running search when set, the best move found so far is returned.
+    /// It stays set until cleared, so a stop sent before the search got going is not lost.
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Searches with iterative deepening until a limit is hit, calling `report` after every
+    /// finished iteration. Returns `None` only if the side to move has no legal moves.
+    pub fn search(
+        &mut self,
+        state: &GameState,
+        limits: &Limits,
+        mut report: impl FnMut(&SearchInfo),
+    ) -> Option<(Position, Move)> {
+        let started = Instant::now();
+        let mut root = state.clone();
+        self.nodes = 0;
+        self.node_limit = limits.nodes;
+        self.deadline = limits.movetime.map(|time| started + time);
+        self.aborted = false;
+        self.path = vec![root.hash()];
+        self.tt.new_search();
+
+        let mut moves = vec![];
+        root.generate_moves(root.active_color(), !0, &mut moves);
+        let mut best = *moves.first()?;
+
+        let max_depth = limits
+            .depth
+            .unwrap_or(MAX_PLY as u32 - 1)
+            .clamp(1, MAX_PLY as u32 - 1);
+        for depth in 1..=max_depth {
+            let score = self.negamax(&mut root, depth, 0, -INFINITY, INFINITY, Some(best));
+            if self.aborted {
+                break;
+            }
+            if let Some(first) = self.pv[0].first() {
+                best = *first;
+            }
+
+            report(&SearchInfo {
+                depth,
+                score,
+                nodes: self.nodes,
+                time: started.elapsed(),
+                pv: self.pv[0].clone(),
+                hashfull: self.tt.hashfull(),
+            });
+
+            // a mate within the searched depth will not get any better
+            if MATE - score.abs() <= depth as i32 {
+                break;
+            }
+        }
+
+        Some(best)
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            self.aborted |= self.stop.load(Ordering::Relaxed)
+                || self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline)
+                || self.node_limit.is_some_and(|limit| self.nodes >= limit);
+        }
+        self.aborted
+    }
+
+    fn is_draw(&self, state: &GameState) -> bool {
+        let key = state.hash();
+        state.halfmove_clock() >= 100
+            || state.occurrences(key) > 0
+            || self.path[..self.path.len() - 1].contains(&key)
+    }
+
+    fn negamax(
+        &mut self,
+        state: &mut GameState,
+        depth: u32,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+        mut preferred: Option<(Position, Move)>,
+    ) -> i32 {
+        self.pv[ply].clear();
+        if ply > 0 && self.is_draw(state) {
+            return 0;
+        }
+        if depth == 0 || ply >= MAX_PLY - 1 {
+            return self.quiescence(state, ply, alpha, beta);
+        }
+
+        self.nodes += 1;
+        if self.should_stop() {
+            return 0;
+        }
+
+        let key = state.hash();
+        if let Some(entry) = self.tt.probe(key) {
+            let score = entry.score(ply);
+            // the root always searches, so there is a move to play
+            if ply > 0 && entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => {}
+                }
+            }
+            preferred = preferred.or(entry.best);
+        }
+
+        let mut moves = vec![];
+        state.generate_moves(state.active_color(), !0, &mut moves);
+        // the variant knows how its games end, e.g. with a king that was blown up
+        if let Some(phase) = state.variant().game_end(state, !moves.is_empty()) {
+            return game_end_score(state, phase, ply);
+        }
+        self.order_moves(state, &mut moves, ply, preferred);
+
+        let original_alpha = alpha;
+        let mut best = -INFINITY;
+        let mut best_move = None;
+        for (from, m) in moves {
+            let quiet = !is_tactical(state, m);
+            let to = m.target(state.active_color());
+
+            let unmake = state.make_move(from, m);
+            self.path.push(state.hash());
+            let score = -self.negamax(state, depth - 1, ply + 1, -beta, -alpha, None);
+            self.path.pop();
+            state.unmake_move(from, m, unmake);
+
+            if self.aborted {
+                return 0;
+            }
+            if score > best {
+                best = score;
+            }
+            if score > alpha {
+                alpha = score;
+                best_move = Some((from, m));
+                let (head, tail) = self.pv.split_at_mut(ply + 1);
+                head[ply].clear();
+                head[ply].push((from, m));
+                head[ply].extend_from_slice(&tail[0]);
+            }
+            if alpha >= beta {
+                if quiet {
+                    let killers = &mut self.killers[ply];
+                    if killers[0] != Some((from, m)) {
+                        killers[1] = killers[0];
+                        killers[0] = Some((from, m));
+                    }
+                    let history = &mut self.history[from.idx()][to.idx()];
+                    *history = history.saturating_add(depth * depth);
+                }
+                break;
+            }
+        }
+
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt.store(key, depth, bound, best, best_move, ply);
+        best
+    }
+
+    /// Resolves captures and promotions, so the evaluation is not taken in the middle of an exchange.
+    fn quiescence(&mut self, state: &mut GameState, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.nodes += 1;
+        if self.should_stop() {
+            return 0;
+        }
+
+        // without generating the moves only the ends that do not depend on them are seen,
+        // like a king on the hill or a dead position, mates are left to the full search
+        if let Some(phase) = state.variant().game_end(state, true) {
+            return game_end_score(state, phase, ply);
+        }
+
+        let stand_pat = self.evaluator.evaluate(state);
+        if stand_pat >= beta || ply >= MAX_PLY - 1 {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let mut moves = vec![];
+        state.generate_moves(state.active_color(), !0, &mut moves);
+        moves.retain(|(_, m)| is_tactical(state, *m));
+        self.order_moves(state, &mut moves, ply, None);
+
+        for (from, m) in moves {
+            let unmake = state.make_move(from, m);
+            let score = -self.quiescence(state, ply + 1, -beta, -alpha);
+            state.unmake_move(from, m, unmake);
+
+            if self.aborted {
+                return 0;
+            }
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+
+        alpha
+    }
+
+    /// Sorts the `preferred` move first, then captures by MVV-LVA, promotions,
+    /// killer moves and the remaining quiet moves by their history score.
+    fn order_moves(
+        &self,
+        state: &GameState,
+        moves: &mut [(Position, Move)],
+        ply: usize,
+        preferred: Option<(Position, Move)>,
+    ) {
+        let player = state.active_color();
+        moves.sort_by_cached_key(|(from, m)| {
+            let attacker = state.piece_at(*from).map_or(0, |p| piece_value(p.kind));
+            let score = if preferred == Some((*from, *m)) {
+                1_000_000
+            } else if let Some(victim) = victim(state, *m) {
+                100_000 + piece_value(victim) * 10 - attacker / 10
+            } else if let Move::Promote(_, promoted) = m {
+                90_000 + piece_value(promoted.kind)
+            } else if self.killers[ply][0] == Some((*from, *m)) {
+                80_000
+            } else if self.killers[ply][1] == Some((*from, *m)) {
+                79_000
+            } else {
+                self.history[from.idx()][m.target(player).idx()].min(78_000) as i32
+            };
+            -score
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::search::{Engine, Limits, MATE};
+    use crate::state::game::{
+        DrawReason, GamePhase, GameState, Move, PlayerColor, Position, WinReason,
+    };
+    use crate::state::variant::{Atomic, KingOfTheHill, ThreeCheck, Variant};
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, Instant};
+
+    fn search(state: &GameState, depth: u32) -> ((Position, Move), i32) {
+        let mut score = 0;
+        let limits = Limits {
+            depth: Some(depth),
+            ..Limits::default()
+        };
+        let best = Engine::new().search(state, &limits, |info| score = info.score);
+        (best.unwrap(), score)
+    }
+
+    fn play(mut state: GameState, moves: &[&str]) -> GameState {
+        for san in moves {
+            let (from, m) = state.parse_san(san).unwrap();
+            state = state.apply(from, m).unwrap();
+        }
+        state
+    }
+
+    fn from_fen(fen: &str, variant: &'static dyn Variant) -> GameState {
+        GameState::from_fen_with_variant(fen, variant).unwrap()
+    }
+
+    #[test]
+    fn mate_in_one() {
+        let state = GameState::from_fen("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        let ((from, m), score) = search(&state, 3);
+        assert_eq!(state.to_san(from, m), "Ra8#");
+        assert_eq!(score, MATE - 1);
+    }
+
+    #[test]
+    fn mate_in_two() {
+        // the rook on b7 takes the seventh rank, the other one mates on the eighth
+        let state = GameState::from_fen("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1").unwrap();
+        let (_, score) = search(&state, 4);
+        assert_eq!(score, MATE - 3);
+
+        // and the side getting mated knows
+        let state = play(state, &["Rb7"]);
+        let (_, score) = search(&state, 4);
+        assert_eq!(score, -MATE + 2);
+    }
+
+    #[test]
+    fn takes_a_hanging_piece() {
+        let state = GameState::from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+        let ((from, m), score) = search(&state, 3);
+        assert_eq!(state.to_san(from, m), "Rxd5");
+        assert!(score > 300);
+    }
+
+    #[test]
+    fn draws() {
+        // taking the rook with the queen would leave black without a move
+        let state = GameState::from_fen("k7/2r5/1K6/8/8/8/8/2Q5 w - - 0 1").unwrap();
+        let ((from, m), score) = search(&state, 3);
+        let after = state.apply(from, m).unwrap();
+        assert!(!matches!(
+            after.phase(),
+            GamePhase::Draw(DrawReason::Stalemate)
+        ));
+        assert!(score > 500);
+
+        // a queen up is nothing once the fifty moves are up
+        let state = GameState::from_fen("4k3/8/8/8/8/8/8/Q3K3 w - - 99 80").unwrap();
+        assert_eq!(search(&state, 3).1, 0);
+
+        // with no move at all there is nothing to play
+        let stalemate = GameState::from_fen("k7/P7/1K6/8/8/8/8/8 b - - 0 1").unwrap();
+        let limits = Limits {
+            depth: Some(3),
+            ..Limits::default()
+        };
+        assert_eq!(Engine::new().search(&stalemate, &limits, |_| {}), None);
+    }
+
+    #[test]
+    fn limits() {
+        let state = GameState::new();
+        let started = Instant::now();
+        let movetime = Limits {
+            movetime: Some(Duration::from_millis(100)),
+            ..Limits::default()
+        };
+        assert!(Engine::new().search(&state, &movetime, |_| {}).is_some());
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let mut nodes = 0;
+        let limits = Limits {
+            nodes: Some(5_000),
+            ..Limits::default()
+        };
+        Engine::new().search(&state, &limits, |info| nodes = info.nodes);
+        assert!(nodes <= 5_000 + 2048);
+
+        // a search without limits runs until stopped from outside
+        let mut engine = Engine::new();
+        let stop = engine.stop_handle();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            stop.store(true, Ordering::Relaxed);
+        });
+        let started = Instant::now();
+        assert!(engine.search(&state, &Limits::default(), |_| {}).is_some());
+        assert!(started.elapsed() < Duration::from_secs(2));
+        stopper.join().unwrap();
+    }
+
+    #[test]
+    fn atomic_explosion_wins() {
+        // taking the pawn next to the king blows it up, which no other move comes close to
+        let state = from_fen("4k3/4p3/8/8/8/8/3r4/4QK2 w - - 0 1", &Atomic);
+        let ((from, m), score) = search(&state, 3);
+        assert_eq!(state.to_san(from, m), "Qxe7#");
+        assert_eq!(score, MATE - 1);
+    }
+
+    #[test]
+    fn king_of_the_hill_wins() {
+        // a bare king is no dead position when it can walk onto the hill
+        let state = from_fen("8/8/8/8/8/2K5/8/k7 w - - 0 1", &KingOfTheHill);
+        let ((from, m), score) = search(&state, 3);
+        assert_eq!(state.to_san(from, m), "Kd4");
+        assert_eq!(score, MATE - 1);
+    }
+
+    #[test]
+    fn third_check_wins() {
+        let state = from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", &ThreeCheck);
+        let state = play(state, &["Ra8+", "Ke7", "Ra7+", "Ke6"]);
+        let ((from, m), score) = search(&state, 3);
+        assert_eq!(score, MATE - 1);
+        assert!(matches!(
+            state.apply(from, m).unwrap().phase(),
+            GamePhase::Won(PlayerColor::White, WinReason::ThreeChecks)
+        ));
+    }
+}
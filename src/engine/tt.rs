@@ -0,0 +1,266 @@
+use crate::engine::search::{MATE, MAX_PLY};
+use crate::state::game::{Move, Position};
+use std::mem::size_of;
+
+/// Scores this close to [MATE] are mates, stored relative to the position instead of the root.
+const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+
+/// How a stored score relates to the real one.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Bound {
+    Exact,
+    /// The search failed high, the score is at least this.
+    Lower,
+    /// The search failed low, the score is at most this.
+    Upper,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Entry {
+    key: u64,
+    pub depth: u32,
+    pub bound: Bound,
+    score: i32,
+    pub best: Option<(Position, Move)>,
+    generation: u8,
+}
+
+impl Entry {
+    /// The stored score, with mates counted from the root again for a position `ply` deep.
+    pub fn score(&self, ply: usize) -> i32 {
+        if self.score >= MATE_BOUND {
+            self.score - ply as i32
+        } else if self.score <= -MATE_BOUND {
+            self.score + ply as i32
+        } else {
+            self.score
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct TableStats {
+    pub probes: u64,
+    pub hits: u64,
+    pub stores: u64,
+    /// Stores that pushed out an entry of another position.
+    pub replacements: u64,
+}
+
+impl TableStats {
+    pub fn hit_rate(&self) -> f64 {
+        if self.probes == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.probes as f64
+        }
+    }
+}
+
+/// A fixed-size hash table of search results, keyed by [GameState::hash](crate::state::game::GameState::hash).
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+    generation: u8,
+    stats: TableStats,
+}
+
+impl TranspositionTable {
+    pub fn new(megabytes: usize) -> Self {
+        let mut table = Self {
+            entries: vec![],
+            generation: 0,
+            stats: TableStats::default(),
+        };
+        table.resize(megabytes);
+        table
+    }
+
+    /// Reallocates the table with as many entries as fit in `megabytes`, forgetting all of them.
+    pub fn resize(&mut self, megabytes: usize) {
+        let count = (megabytes * 1024 * 1024 / size_of::<Option<Entry>>()).max(1);
+        self.entries = vec![None; count];
+        self.stats = TableStats::default();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|entry| *entry = None);
+        self.generation = 0;
+        self.stats = TableStats::default();
+    }
+
+    /// Marks everything stored so far as belonging to an older search, so it is replaced first,
+    /// and starts counting statistics over.
+    pub fn new_search(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.stats = TableStats::default();
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+
+    pub fn probe(&mut self, key: u64) -> Option<Entry> {
+        self.stats.probes += 1;
+        let entry = self.entries[self.index(key)].filter(|entry| entry.key == key)?;
+        self.stats.hits += 1;
+        Some(entry)
+    }
+
+    /// Stores a result found `ply` plies from the root. An entry of another position is only
+    /// replaced if it comes from an older search or was searched less deep.
+    pub fn store(
+        &mut self,
+        key: u64,
+        depth: u32,
+        bound: Bound,
+        score: i32,
+        best: Option<(Position, Move)>,
+        ply: usize,
+    ) {
+        let index = self.index(key);
+        if let Some(old) = self.entries[index] {
+            if old.key != key {
+                if old.generation == self.generation && old.depth > depth {
+                    return;
+                }
+                self.stats.replacements += 1;
+            }
+        }
+
+        let score = if score >= MATE_BOUND {
+            score + ply as i32
+        } else if score <= -MATE_BOUND {
+            score - ply as i32
+        } else {
+            score
+        };
+        // a result without a move should not forget the move found earlier
+        let best = best.or_else(|| {
+            self.entries[index]
+                .filter(|old| old.key == key)
+                .and_then(|old| old.best)
+        });
+
+        self.stats.stores += 1;
+        self.entries[index] = Some(Entry {
+            key,
+            depth,
+            bound,
+            score,
+            best,
+            generation: self.generation,
+        });
+    }
+
+    pub fn stats(&self) -> TableStats {
+        self.stats
+    }
+
+    /// How full the table is with entries of the current search, in permille.
+    pub fn hashfull(&self) -> u32 {
+        let sample = self.entries.len().min(1000);
+        let used = self.entries[..sample]
+            .iter()
+            .filter(|entry| entry.is_some_and(|entry| entry.generation == self.generation))
+            .count();
+        (used * 1000 / sample) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::search::MATE;
+    use crate::engine::tt::{Bound, TableStats, TranspositionTable};
+    use crate::state::game::{Move, Position};
+
+    const E4: Option<(Position, Move)> = Some((Position(4, 6), Move::Move(Position(4, 4))));
+
+    #[test]
+    fn mate_scores_count_from_the_probing_node() {
+        let mut table = TranspositionTable::new(1);
+        // a mate found 3 plies from the root is 2 plies away from the stored position
+        table.store(1, 4, Bound::Exact, MATE - 5, None, 3);
+        let entry = table.probe(1).unwrap();
+        assert_eq!(entry.score(3), MATE - 5);
+        assert_eq!(entry.score(1), MATE - 3);
+        assert_eq!(entry.score(6), MATE - 8);
+
+        table.store(2, 4, Bound::Upper, -MATE + 4, None, 2);
+        assert_eq!(table.probe(2).unwrap().score(5), -MATE + 7);
+
+        // other scores stay as they are
+        table.store(3, 4, Bound::Lower, 250, None, 7);
+        assert_eq!(table.probe(3).unwrap().score(1), 250);
+    }
+
+    #[test]
+    fn replacement() {
+        let mut table = TranspositionTable::new(1);
+        let len = table.entries.len() as u64;
+        let (key, rival) = (5, 5 + len);
+
+        table.store(key, 6, Bound::Exact, 10, E4, 0);
+        // a shallower result of another position does not push out a deeper one
+        table.store(rival, 2, Bound::Exact, 20, None, 0);
+        assert!(table.probe(rival).is_none());
+        assert_eq!(table.probe(key).unwrap().depth, 6);
+
+        // the same position is always updated, keeping its move
+        table.store(key, 1, Bound::Lower, 30, None, 0);
+        let entry = table.probe(key).unwrap();
+        assert_eq!((entry.depth, entry.score(0), entry.best), (1, 30, E4));
+
+        table.store(rival, 3, Bound::Exact, 20, None, 0);
+        assert!(table.probe(key).is_none());
+        assert_eq!(table.probe(rival).unwrap().depth, 3);
+
+        // anything from an earlier search goes, however deep
+        table.store(key, 9, Bound::Exact, 10, None, 0);
+        table.new_search();
+        table.store(rival, 1, Bound::Exact, 20, None, 0);
+        assert_eq!(table.probe(rival).unwrap().depth, 1);
+        assert_eq!(table.stats().replacements, 1);
+    }
+
+    #[test]
+    fn stats() {
+        let mut table = TranspositionTable::new(1);
+        assert_eq!(table.stats().hit_rate(), 0.0);
+        table.store(1, 1, Bound::Exact, 0, None, 0);
+        table.probe(1);
+        table.probe(2);
+        table.probe(1);
+        table.probe(3);
+        let stats = table.stats();
+        assert_eq!(
+            stats,
+            TableStats {
+                probes: 4,
+                hits: 2,
+                stores: 1,
+                replacements: 0
+            }
+        );
+        assert_eq!(stats.hit_rate(), 0.5);
+
+        // every search counts on its own, the entries stay
+        table.new_search();
+        assert_eq!(table.stats(), TableStats::default());
+        assert!(table.probe(1).is_some());
+        table.clear();
+        assert!(table.probe(1).is_none());
+    }
+
+    #[test]
+    fn hashfull() {
+        let mut table = TranspositionTable::new(1);
+        assert_eq!(table.hashfull(), 0);
+        for key in 0..100 {
+            table.store(key, 1, Bound::Exact, 0, None, 0);
+        }
+        assert_eq!(table.hashfull(), 100);
+        // only entries of the current search count
+        table.new_search();
+        assert_eq!(table.hashfull(), 0);
+    }
+}
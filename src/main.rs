@@ -1,22 +1,100 @@
+use crate::engine::eval::{TaperedEval, Weights};
+use crate::engine::search::{Engine, Limits};
+use crate::protocol::uci::pv_notation;
+use crate::protocol::uci_client::{EngineReply, Score, UciClient, UciError};
+use crate::state::bitboard::color_index;
 use crate::state::board::PieceKind;
-use crate::state::game::{GamePhase, GameState, Interaction, PlayerColor};
+use crate::state::chess960::CHESS960_POSITIONS;
+use crate::state::clock::{format_time, TimeControl};
+use crate::state::game::{GamePhase, GameState, Interaction, Move, PlayerColor, Position};
+use crate::state::pgn::{parse_pgn, PgnTags};
+use crate::state::variant::{variant_by_name, Standard, Variant};
 use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::mouse::MouseButton;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
+mod engine;
+mod protocol;
 mod state;
 mod ui;
 
 use crate::ui::board::BoardRenderer;
+use crate::ui::button::ButtonRenderer;
 use crate::ui::promotion::PromotionRenderer;
 use crate::ui::text::{FontRenderer, TextRenderer};
 
 const SCREEN_WIDTH: u32 = 800;
 const SCREEN_HEIGHT: u32 = 600;
+const SAVED_GAME_FILE: &str = "game.pgn";
+const COMPUTER_MOVETIME: Duration = Duration::from_secs(1);
+const USAGE: &str = "Usage: chess [fen | game.pgn] [--clock <time control>] [--hash <megabytes>] \
+    [--chess960 <0-959 | random>] [--variant <kingofthehill | threecheck | crazyhouse | atomic>] [--white-engine <uci engine>] [--black-engine <uci engine>] [--search-info] | chess --uci | chess --xboard";
+const EVAL_WEIGHTS_FILE: &str = "./assets/eval.conf";
+
+/// A search running in the background, for the position with the given key and ply count.
+/// The engine is handed back with the result, so it keeps what it learned for the next move.
+struct Thinking {
+    key: u64,
+    plies: usize,
+    result: Receiver<(Engine, Option<(Position, Move)>)>,
+    stop: Arc<AtomicBool>,
+}
 
 fn main() -> Result<(), String> {
+    let mut args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) == Some("perft") {
+        return perft(&args[2..]);
+    }
+    if args.iter().any(|arg| arg == "--uci") {
+        return protocol::uci::run(load_weights());
+    }
+    if args.iter().any(|arg| arg == "--xboard") {
+        return protocol::xboard::run(load_weights());
+    }
+    let time_control = take_option(&mut args, "--clock")?
+        .map(|spec| TimeControl::parse(&spec))
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    let hash_size = take_option(&mut args, "--hash")?
+        .map(|size| size.parse::<usize>())
+        .transpose()
+        .map_err(|_| USAGE)?;
+    let chess960 = take_option(&mut args, "--chess960")?
+        .map(|index| match index.as_str() {
+            "random" => Ok(random_chess960_index()),
+            index => index.parse::<u16>(),
+        })
+        .transpose()
+        .map_err(|_| USAGE)?;
+    let variant = match take_option(&mut args, "--variant")? {
+        Some(name) => variant_by_name(&name).ok_or(format!("Unknown variant {}", name))?,
+        None => &Standard,
+    };
+    if chess960.is_some() && variant.name() != Standard.name() {
+        return Err(format!("{} cannot be played as Chess960", variant.name()));
+    }
+    // what the computer thinks goes to stderr, for debugging the engine
+    let search_info = take_flag(&mut args, "--search-info");
+    // engines in other processes, by color index
+    let mut external_engines: [Option<UciClient>; 2] = [None, None];
+    for (color, flag) in [
+        (PlayerColor::White, "--white-engine"),
+        (PlayerColor::Black, "--black-engine"),
+    ] {
+        if let Some(program) = take_option(&mut args, flag)? {
+            let client =
+                UciClient::spawn(&program, &[]).map_err(|e| format!("{}: {}", program, e))?;
+            println!("{:?} is played by {}", color, client.name());
+            external_engines[color_index(color)] = Some(client);
+        }
+    }
+
     println!("Instantiating Game!");
 
     let context = sdl2::init()?;
@@ -50,6 +128,7 @@ fn main() -> Result<(), String> {
     let board_ui = BoardRenderer::new(
         Rect::new(0, 0, SCREEN_WIDTH, SCREEN_HEIGHT),
         &chess_font_renderer,
+        &text_font_renderer,
     );
     let promotion_ui = PromotionRenderer::new(
         Rect::new(0, 0, SCREEN_WIDTH, SCREEN_HEIGHT),
@@ -61,7 +140,29 @@ fn main() -> Result<(), String> {
         .event_pump()
         .expect("Failed to fetch the event queue!");
 
-    let mut game_state = GameState::new();
+    let custom_variant = variant.name() != Standard.name();
+    let mut game_state = match args.get(1) {
+        // a game brings its own variant along in its tags
+        Some(path) if path.ends_with(".pgn") => {
+            if chess960.is_some() || custom_variant {
+                return Err("A saved game cannot be played as another variant".to_string());
+            }
+            load_pgn(path)?
+        }
+        // the castling rights of a FEN already tell whether it is Chess960
+        Some(_) if chess960.is_some() => {
+            return Err("A FEN cannot be combined with --chess960".to_string());
+        }
+        Some(fen) => GameState::from_fen_with_variant(fen, variant).map_err(|e| e.to_string())?,
+        None => match chess960 {
+            Some(index) => GameState::new_chess960(index).ok_or(USAGE)?,
+            None => GameState::with_variant(variant),
+        },
+    };
+    if let Some(control) = time_control {
+        game_state = game_state.with_clock(control);
+    }
+    let clipboard = video_subsystem.clipboard();
 
     let fps_label = TextRenderer::new(
         &text_font_renderer,
@@ -71,6 +172,57 @@ fn main() -> Result<(), String> {
         &text_font_renderer,
         Rect::new(SCREEN_WIDTH as i32 - 100, 32, 100, 32),
     );
+    let variant_label = TextRenderer::new(
+        &text_font_renderer,
+        Rect::new(SCREEN_WIDTH as i32 - 100, 184, 100, 32),
+    );
+    let variant_status = TextRenderer::new(
+        &text_font_renderer,
+        Rect::new(SCREEN_WIDTH as i32 - 100, 216, 100, 32),
+    );
+    let black_clock = TextRenderer::new(&text_font_renderer, Rect::new(0, 0, 100, 32));
+    let white_clock = TextRenderer::new(
+        &text_font_renderer,
+        Rect::new(0, SCREEN_HEIGHT as i32 - 32, 100, 32),
+    );
+    let computer_label = TextRenderer::new(
+        &text_font_renderer,
+        Rect::new(SCREEN_WIDTH as i32 - 100, 400, 100, 32),
+    );
+    let computer_buttons = [
+        (None, "Off", 436),
+        (Some(PlayerColor::White), "White", 472),
+        (Some(PlayerColor::Black), "Black", 508),
+    ]
+    .map(|(color, label, y)| {
+        let area = Rect::new(SCREEN_WIDTH as i32 - 96, y, 92, 32);
+        (color, ButtonRenderer::new(&text_font_renderer, area, label))
+    });
+    let weights = load_weights();
+    let mut computer: Option<PlayerColor> = None;
+    let mut thinking: Option<Thinking> = None;
+    let mut idle_engine = None;
+
+    let resign_button = ButtonRenderer::new(
+        &text_font_renderer,
+        Rect::new(SCREEN_WIDTH as i32 - 96, 72, 92, 32),
+        "Resign",
+    );
+    let offer_draw_button = ButtonRenderer::new(
+        &text_font_renderer,
+        Rect::new(SCREEN_WIDTH as i32 - 96, 108, 92, 32),
+        "Offer draw",
+    );
+    let accept_draw_button = ButtonRenderer::new(
+        &text_font_renderer,
+        Rect::new(SCREEN_WIDTH as i32 - 96, 108, 92, 32),
+        "Accept",
+    );
+    let decline_draw_button = ButtonRenderer::new(
+        &text_font_renderer,
+        Rect::new(SCREEN_WIDTH as i32 - 96, 144, 92, 32),
+        "Decline",
+    );
 
     let mut now = Instant::now();
     let mut fps = 0.0;
@@ -79,11 +231,69 @@ fn main() -> Result<(), String> {
 
     'game_loop: loop {
         frames += 1;
+        let external_to_move = match game_state.phase() {
+            GamePhase::Turn(player) => external_engines[color_index(player)].is_some(),
+            _ => false,
+        };
+        let computer_to_move = match game_state.phase() {
+            GamePhase::Turn(player) => computer == Some(player) && !external_to_move,
+            _ => false,
+        };
         for event in event_queue.poll_iter() {
             match event {
                 Event::Quit { .. } => {
                     break 'game_loop;
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::C),
+                    keymod,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    clipboard.set_clipboard_text(&game_state.to_fen())?;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::V),
+                    keymod,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    let fen = clipboard.clipboard_text()?;
+                    match GameState::from_fen_with_variant(&fen, game_state.variant()) {
+                        Ok(state) => game_state = state,
+                        Err(e) => println!("Could not load position: {}", e),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::S),
+                    keymod,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    let tags = PgnTags::default().dated(SystemTime::now());
+                    match std::fs::write(SAVED_GAME_FILE, game_state.to_pgn(&tags)) {
+                        Ok(_) => println!("Saved game to {}", SAVED_GAME_FILE),
+                        Err(e) => println!("Could not save game: {}", e),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Z),
+                    keymod,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    game_state = game_state.undo();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Y),
+                    keymod,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    game_state = game_state.redo();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::D),
+                    keymod,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    game_state = game_state.interact(Interaction::ClaimDraw);
+                }
                 Event::MouseMotion { x, y, .. } => {
                     mouse_position = (x, y);
                 }
@@ -98,6 +308,36 @@ fn main() -> Result<(), String> {
                             game_state = game_state.interact(Interaction::PickedPromotion(piece));
                         }
                     }
+                    _ if computer_buttons.iter().any(|(_, b)| b.mouse_is_over(x, y)) => {
+                        for (color, button) in &computer_buttons {
+                            if button.mouse_is_over(x, y) {
+                                computer = *color;
+                            }
+                        }
+                    }
+                    _ if computer_to_move || external_to_move => {}
+                    _ if board_ui.pocket_piece_at(x, y).is_some() => {
+                        if let Some(piece) = board_ui.pocket_piece_at(x, y) {
+                            game_state =
+                                game_state.interact(Interaction::StartDroppingPiece(piece));
+                        }
+                    }
+                    _ if resign_button.mouse_is_over(x, y) => {
+                        game_state = game_state.interact(Interaction::Resign);
+                    }
+                    _ if game_state.draw_offer() == Some(game_state.active_color().opponent()) => {
+                        if accept_draw_button.mouse_is_over(x, y) {
+                            game_state = game_state.interact(Interaction::AcceptDraw);
+                        } else if decline_draw_button.mouse_is_over(x, y) {
+                            game_state = game_state.interact(Interaction::DeclineDraw);
+                        } else if let Some(position) = board_ui.mouse_is_over(x, y) {
+                            game_state =
+                                game_state.interact(Interaction::StartMovingPiece(position));
+                        }
+                    }
+                    _ if offer_draw_button.mouse_is_over(x, y) => {
+                        game_state = game_state.interact(Interaction::OfferDraw);
+                    }
                     _ => {
                         if let Some(position) = board_ui.mouse_is_over(x, y) {
                             game_state =
@@ -119,6 +359,106 @@ fn main() -> Result<(), String> {
             }
         }
 
+        game_state = game_state.update_clock(Instant::now());
+
+        if computer_to_move && thinking.is_none() {
+            let (sender, result) = channel();
+            let state = game_state.clone();
+            let mut engine = idle_engine.take().unwrap_or_else(|| {
+                let mut engine =
+                    Engine::with_evaluator(Box::new(TaperedEval::new(weights.clone())));
+                if let Some(size) = hash_size {
+                    engine.set_hash_size(size);
+                }
+                engine
+            });
+            let stop = engine.stop_handle();
+            stop.store(false, Ordering::Relaxed);
+            std::thread::spawn(move || {
+                let limits = Limits {
+                    movetime: Some(COMPUTER_MOVETIME),
+                    ..Limits::default()
+                };
+                let found = engine.search(&state, &limits, |info| {
+                    if !search_info {
+                        return;
+                    }
+                    let score = match info.mate_in() {
+                        Some(moves) => format!("mate {}", moves),
+                        None => format!("{} cp", info.score),
+                    };
+                    let pv = pv_notation(&state, &info.pv);
+                    eprintln!(
+                        "depth {} score {} nodes {} time {}ms hashfull {} pv {}",
+                        info.depth,
+                        score,
+                        info.nodes,
+                        info.time.as_millis(),
+                        info.hashfull,
+                        pv.join(" ")
+                    );
+                });
+                if search_info {
+                    let stats = engine.hash_stats();
+                    eprintln!(
+                        "hash probes {} hit rate {:.1}% replacements {}",
+                        stats.probes,
+                        stats.hit_rate() * 100.0,
+                        stats.replacements
+                    );
+                }
+                // the receiver is gone if the game moved on meanwhile
+                let _ = sender.send((engine, found));
+            });
+            thinking = Some(Thinking {
+                key: game_state.hash(),
+                plies: game_state.history().len(),
+                result,
+                stop,
+            });
+        }
+        for (color, slot) in [PlayerColor::Black, PlayerColor::White]
+            .into_iter()
+            .zip(external_engines.iter_mut())
+        {
+            let Some(client) = slot else {
+                continue;
+            };
+            match drive_external_engine(client, &game_state, color) {
+                Ok(Some((from, m))) => match game_state.apply(from, m) {
+                    Ok(next) => game_state = next,
+                    Err(e) => println!("{} played an illegal move: {}", client.name(), e),
+                },
+                Ok(None) => {}
+                Err(e) => {
+                    println!("{} stopped playing {:?}: {}", client.name(), color, e);
+                    *slot = None;
+                }
+            }
+        }
+
+        if let Some(search) = &thinking {
+            match search.result.try_recv() {
+                Ok((engine, found)) => {
+                    idle_engine = Some(engine);
+                    let unchanged = game_state.hash() == search.key
+                        && game_state.history().len() == search.plies;
+                    if let (Some((from, m)), true) = (found, unchanged && computer_to_move) {
+                        match game_state.apply(from, m) {
+                            Ok(next) => game_state = next,
+                            Err(e) => println!("Computer played an illegal move: {}", e),
+                        }
+                    }
+                    thinking = None;
+                }
+                Err(TryRecvError::Empty) if !computer_to_move => {
+                    search.stop.store(true, Ordering::Relaxed);
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => thinking = None,
+            }
+        }
+
         let elapsed = now.elapsed().as_secs_f64();
 
         if elapsed >= 0.1 {
@@ -134,20 +474,191 @@ fn main() -> Result<(), String> {
 
         fps_label.render(format!("FPS: {:.0}", fps).as_str(), &mut canvas)?;
         match game_state.phase() {
-            GamePhase::Won(p) => {
-                current_player.render(format!("Winner: {:?}", p).as_str(), &mut canvas)?
-            }
+            GamePhase::Won(p, reason) => current_player.render(
+                format!("Winner: {:?} by {}", p, reason).as_str(),
+                &mut canvas,
+            )?,
             GamePhase::Draw(reason) => {
-                current_player.render(format!("Draw: {:?}", reason).as_str(), &mut canvas)?
+                current_player.render(format!("Draw: {}", reason).as_str(), &mut canvas)?
             }
             GamePhase::Turn(p) => {
                 current_player.render(format!("Turn: {:?}", p).as_str(), &mut canvas)?
             }
         };
 
+        let variant = game_state.variant();
+        if variant.name() != Standard.name() {
+            variant_label.render(variant.name(), &mut canvas)?;
+        }
+        if let Some(status) = variant.status(&game_state) {
+            variant_status.render(status.as_str(), &mut canvas)?;
+        }
+
+        if let Some(clock) = game_state.clock() {
+            let now = Instant::now();
+            for (color, label) in [
+                (PlayerColor::White, &white_clock),
+                (PlayerColor::Black, &black_clock),
+            ] {
+                let time = format_time(clock.remaining(color, now));
+                label.render(format!("{:?} {}", color, time).as_str(), &mut canvas)?;
+            }
+        }
+
+        let computer_name = computer.map_or("Off".to_string(), |c| format!("{:?}", c));
+        computer_label.render(format!("Computer: {}", computer_name).as_str(), &mut canvas)?;
+        for (_, button) in &computer_buttons {
+            button.render(&mut canvas, mouse_position)?;
+        }
+
+        if let GamePhase::Turn(_) = game_state.phase() {
+            resign_button.render(&mut canvas, mouse_position)?;
+            if game_state.draw_offer() == Some(game_state.active_color().opponent()) {
+                accept_draw_button.render(&mut canvas, mouse_position)?;
+                decline_draw_button.render(&mut canvas, mouse_position)?;
+            } else {
+                offer_draw_button.render(&mut canvas, mouse_position)?;
+            }
+        }
+
         canvas.present();
     }
 
     println!("Game Terminated!");
     Ok(())
 }
+
+/// `chess perft <depth> [fen]`, prints the node count below every legal move.
+fn perft(args: &[String]) -> Result<(), String> {
+    let depth = args
+        .first()
+        .and_then(|depth| depth.parse::<u32>().ok())
+        .ok_or("Usage: chess perft <depth> [fen]")?;
+    let state = match args.get(1) {
+        Some(fen) => GameState::from_fen(fen).map_err(|e| e.to_string())?,
+        None => GameState::new(),
+    };
+
+    let start = Instant::now();
+    let mut total = 0;
+    for ((from, m), nodes) in state.divide(depth) {
+        println!("{}: {}", state.to_coordinate_notation(from, m), nodes);
+        total += nodes;
+    }
+    let elapsed = start.elapsed();
+
+    println!();
+    println!("Nodes searched: {}", total);
+    println!("Position key: {:016x}", state.hash());
+    println!(
+        "Time: {:.3}s ({:.0} nodes/s)",
+        elapsed.as_secs_f64(),
+        total as f64 / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
+
+/// A Chess960 start position picked from the system time.
+fn random_chess960_index() -> u16 {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|time| time.subsec_nanos())
+        .unwrap_or_default();
+    (nanos % CHESS960_POSITIONS as u32) as u16
+}
+
+/// Removes `flag` and the value following it from the arguments.
+fn take_option(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, String> {
+    match args.iter().position(|arg| arg == flag) {
+        Some(index) if index + 1 < args.len() => {
+            let value = args.remove(index + 1);
+            args.remove(index);
+            Ok(Some(value))
+        }
+        Some(_) => Err(USAGE.into()),
+        None => Ok(None),
+    }
+}
+
+/// Removes `flag` from the arguments, telling whether it was given.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let given = args.iter().any(|arg| arg == flag);
+    args.retain(|arg| arg != flag);
+    given
+}
+
+/// Keeps an external engine searching while it is the turn of `color` and stops it otherwise.
+/// Returns the move it found for the current position once it is done.
+fn drive_external_engine(
+    client: &mut UciClient,
+    game_state: &GameState,
+    color: PlayerColor,
+) -> Result<Option<(Position, Move)>, UciError> {
+    let to_move = matches!(game_state.phase(), GamePhase::Turn(player) if player == color);
+    let is_current = |state: &GameState| {
+        state.hash() == game_state.hash() && state.history().len() == game_state.history().len()
+    };
+    match client.searching() {
+        Some(searching) if !to_move || !is_current(searching) => client.stop()?,
+        None if to_move => {
+            // with a clock the engine manages its own time
+            let limits = Limits {
+                movetime: game_state.clock().is_none().then_some(COMPUTER_MOVETIME),
+                ..Limits::default()
+            };
+            client.go(game_state, &limits)?;
+        }
+        _ => {}
+    }
+
+    let current = client.searching().is_some_and(is_current);
+    while let Some(reply) = client.poll()? {
+        match reply {
+            EngineReply::Info(info) => {
+                let score = match info.score {
+                    Some(Score::Centipawns(cp)) => format!("{} cp", cp),
+                    Some(Score::Mate(moves)) => format!("mate {}", moves),
+                    None => "-".to_string(),
+                };
+                println!(
+                    "{}: depth {} score {} nodes {} time {}ms pv {}",
+                    client.name(),
+                    info.depth.unwrap_or(0),
+                    score,
+                    info.nodes.unwrap_or(0),
+                    info.time.unwrap_or_default().as_millis(),
+                    pv_notation(game_state, &info.pv).join(" ")
+                );
+            }
+            EngineReply::BestMove(best) if current => return Ok(best),
+            EngineReply::BestMove(_) => {}
+        }
+    }
+    Ok(None)
+}
+
+/// The evaluation weights from the assets, or the built-in ones if they cannot be read.
+fn load_weights() -> Weights {
+    Weights::load(EVAL_WEIGHTS_FILE).unwrap_or_else(|e| {
+        // stdout belongs to the protocol in engine mode
+        eprintln!("Using the built-in evaluation weights, {}", e);
+        Weights::default()
+    })
+}
+
+/// Loads the final position of the last game in a PGN file.
+fn load_pgn(path: &str) -> Result<GameState, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let games = parse_pgn(&text).map_err(|e| format!("{}:{}", path, e))?;
+    let game = games
+        .last()
+        .ok_or_else(|| format!("{} contains no games", path))?;
+
+    println!(
+        "Loaded {} vs {} ({})",
+        game.tag("White").unwrap_or("?"),
+        game.tag("Black").unwrap_or("?"),
+        game.result
+    );
+    Ok(game.final_state())
+}
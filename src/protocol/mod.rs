@@ -0,0 +1,50 @@
+use std::fmt::Display;
+use std::io::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+
+pub mod uci;
+pub mod uci_client;
+pub mod xboard;
+
+/// The name the engine introduces itself with, whichever protocol it speaks.
+pub const ENGINE_NAME: &str = "Voideds Chess";
+
+/// Where a protocol sends its replies to the GUI, shared with the search thread.
+#[derive(Clone)]
+pub struct Output(Arc<Mutex<dyn Write + Send>>);
+
+impl Output {
+    pub fn stdout() -> Self {
+        Self(Arc::new(Mutex::new(std::io::stdout())))
+    }
+
+    /// Writes one line and flushes it, the GUI acts on whole lines only.
+    pub fn line(&self, line: impl Display) {
+        let mut output = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let _ = writeln!(output, "{}", line);
+        let _ = output.flush();
+    }
+
+    /// An output kept in memory, to read back what a protocol replied.
+    #[cfg(test)]
+    pub(crate) fn captured() -> (Self, Captured) {
+        let buffer = Arc::new(Mutex::new(vec![]));
+        (Self(buffer.clone()), Captured(buffer))
+    }
+}
+
+#[cfg(test)]
+pub(crate) struct Captured(Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Captured {
+    /// The lines written since the last call.
+    pub(crate) fn take(&self) -> Vec<String> {
+        let written = std::mem::take(&mut *self.0.lock().unwrap());
+        String::from_utf8(written)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
@@ -0,0 +1,451 @@
+use crate::engine::eval::{TaperedEval, Weights};
+use crate::engine::search::{Engine, Limits, SearchInfo, DEFAULT_HASH_SIZE};
+use crate::protocol::{Output, ENGINE_NAME};
+use crate::state::bitboard::color_index;
+use crate::state::game::{GameState, Move, Position};
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const MAX_HASH_SIZE: usize = 4096;
+/// Kept back from every move for talking to the GUI.
+const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+/// The number of moves the remaining time is split over when the GUI does not say.
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+/// Speaks the Universal Chess Interface on stdin and stdout until `quit` or the end of input.
+pub fn run(weights: Weights) -> Result<(), String> {
+    let mut uci = Uci::new(weights, Output::stdout());
+    for line in std::io::stdin().lock().lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if !uci.handle(&line) {
+            break;
+        }
+    }
+    uci.stop_search();
+    Ok(())
+}
+
+/// Describes a principal variation played from `state` in coordinate notation.
+pub fn pv_notation(state: &GameState, pv: &[(Position, Move)]) -> Vec<String> {
+    let mut line = state.clone();
+    let mut notation = vec![];
+    for (from, m) in pv {
+        notation.push(line.to_coordinate_notation(*from, *m));
+        line = line.apply(*from, *m).unwrap_or(line);
+    }
+    notation
+}
+
+struct Uci {
+    weights: Weights,
+    position: GameState,
+    /// Away in the search thread while a search is running.
+    engine: Option<Engine>,
+    search: Option<JoinHandle<Engine>>,
+    stop: Arc<AtomicBool>,
+    /// Whether the GUI asked for Chess960 castling, the king taking its own rook.
+    chess960: bool,
+    output: Output,
+}
+
+impl Uci {
+    fn new(weights: Weights, output: Output) -> Self {
+        let engine = Engine::with_evaluator(Box::new(TaperedEval::new(weights.clone())));
+        Self {
+            weights,
+            position: GameState::new(),
+            stop: engine.stop_handle(),
+            engine: Some(engine),
+            search: None,
+            chess960: false,
+            output,
+        }
+    }
+
+    /// Handles one command from the GUI, returns false once it is time to quit.
+    fn handle(&mut self, line: &str) -> bool {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("uci") => {
+                self.output.line(format!("id name {}", ENGINE_NAME));
+                self.output.line("id author Voided");
+                self.output.line(format!(
+                    "option name Hash type spin default {} min 1 max {}",
+                    DEFAULT_HASH_SIZE, MAX_HASH_SIZE
+                ));
+                self.output.line("option name Clear Hash type button");
+                self.output
+                    .line("option name UCI_Chess960 type check default false");
+                self.output.line("uciok");
+            }
+            Some("isready") => self.output.line("readyok"),
+            Some("ucinewgame") => {
+                self.engine().clear();
+                self.position = GameState::new();
+            }
+            Some("setoption") => self.set_option(tokens),
+            Some("position") => match parse_position(tokens) {
+                Ok(position) if self.chess960 => self.position = position.into_chess960(),
+                Ok(position) => self.position = position,
+                Err(e) => self.output.line(format!("info string {}", e)),
+            },
+            Some("go") => self.go(tokens),
+            Some("stop") => self.stop_search(),
+            Some("quit") => return false,
+            _ => {}
+        }
+        true
+    }
+
+    /// The engine, waiting for a running search to finish first.
+    fn engine(&mut self) -> &mut Engine {
+        if let Some(search) = self.search.take() {
+            self.engine = search.join().ok();
+        }
+        // only a search that panicked loses the engine
+        let weights = &self.weights;
+        let stop = &mut self.stop;
+        self.engine.get_or_insert_with(|| {
+            let engine = Engine::with_evaluator(Box::new(TaperedEval::new(weights.clone())));
+            *stop = engine.stop_handle();
+            engine
+        })
+    }
+
+    fn stop_search(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.engine();
+    }
+
+    fn set_option<'a>(&mut self, mut tokens: impl Iterator<Item = &'a str>) {
+        if tokens.next() != Some("name") {
+            return;
+        }
+        let mut name = vec![];
+        let mut value = vec![];
+        let mut in_value = false;
+        for token in tokens {
+            match token {
+                "value" if !in_value => in_value = true,
+                _ if in_value => value.push(token),
+                _ => name.push(token),
+            }
+        }
+
+        match name.join(" ").to_lowercase().as_str() {
+            "hash" => match value.join(" ").parse::<usize>() {
+                Ok(size) => self.engine().set_hash_size(size.clamp(1, MAX_HASH_SIZE)),
+                Err(_) => self.output.line("info string invalid hash size"),
+            },
+            "clear hash" => self.engine().clear(),
+            "uci_chess960" => match value.join(" ").to_lowercase().as_str() {
+                "true" => self.chess960 = true,
+                "false" => self.chess960 = false,
+                _ => self.output.line("info string invalid UCI_Chess960 value"),
+            },
+            other => self
+                .output
+                .line(format!("info string unknown option '{}'", other)),
+        }
+    }
+
+    fn go<'a>(&mut self, tokens: impl Iterator<Item = &'a str>) {
+        let (limits, infinite) = self.go_limits(tokens);
+
+        // a search still running has to make way
+        self.stop_search();
+        let Some(mut engine) = self.engine.take() else {
+            return;
+        };
+        let state = self.position.clone();
+        let stop = self.stop.clone();
+        let output = self.output.clone();
+        stop.store(false, Ordering::Relaxed);
+        self.search = Some(std::thread::spawn(move || {
+            let best = engine.search(&state, &limits, |info| print_info(&output, &state, info));
+            // the GUI expects no bestmove for an infinite search before it says stop
+            while infinite && !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            match best {
+                Some((from, m)) => output.line(format!(
+                    "bestmove {}",
+                    state.to_coordinate_notation(from, m)
+                )),
+                None => output.line("bestmove 0000"),
+            }
+            engine
+        }));
+    }
+
+    /// Reads the limits of `go`, the time to think being allotted from the clock of the side
+    /// to move unless given outright, and whether to search until told to stop.
+    fn go_limits<'a>(&self, mut tokens: impl Iterator<Item = &'a str>) -> (Limits, bool) {
+        let mut limits = Limits::default();
+        let mut infinite = false;
+        let mut remaining = [None; 2];
+        let mut increment = [Duration::ZERO; 2];
+        let mut moves_to_go = None;
+
+        let millis = |value: Option<&str>| {
+            value
+                .and_then(|value| value.parse::<i64>().ok())
+                .map(|ms| Duration::from_millis(ms.max(0) as u64))
+        };
+        while let Some(token) = tokens.next() {
+            match token {
+                "depth" => limits.depth = tokens.next().and_then(|d| d.parse().ok()),
+                "nodes" => limits.nodes = tokens.next().and_then(|n| n.parse().ok()),
+                "movetime" => limits.movetime = millis(tokens.next()),
+                "wtime" => remaining[1] = millis(tokens.next()),
+                "btime" => remaining[0] = millis(tokens.next()),
+                "winc" => increment[1] = millis(tokens.next()).unwrap_or_default(),
+                "binc" => increment[0] = millis(tokens.next()).unwrap_or_default(),
+                "movestogo" => moves_to_go = tokens.next().and_then(|m| m.parse().ok()),
+                "infinite" => infinite = true,
+                _ => {}
+            }
+        }
+
+        let side = color_index(self.position.active_color());
+        if let (Some(remaining), None) = (remaining[side], limits.movetime) {
+            limits.movetime = Some(allot_time(remaining, increment[side], moves_to_go));
+        }
+        if infinite {
+            limits = Limits::default();
+        }
+        (limits, infinite)
+    }
+}
+
+/// Reads `startpos` or `fen <fen>`, optionally followed by `moves` in coordinate notation.
+fn parse_position<'a>(mut tokens: impl Iterator<Item = &'a str>) -> Result<GameState, String> {
+    let mut state = match tokens.next() {
+        Some("startpos") => GameState::new(),
+        Some("fen") => {
+            let fen = tokens
+                .by_ref()
+                .take_while(|token| *token != "moves")
+                .collect::<Vec<_>>()
+                .join(" ");
+            let state = GameState::from_fen(&fen).map_err(|e| e.to_string())?;
+            // the moves keyword went along with the fen
+            return apply_moves(state, tokens);
+        }
+        _ => return Err("expected startpos or fen".into()),
+    };
+    if tokens.next() == Some("moves") {
+        state = apply_moves(state, tokens)?;
+    }
+    Ok(state)
+}
+
+fn apply_moves<'a>(
+    mut state: GameState,
+    moves: impl Iterator<Item = &'a str>,
+) -> Result<GameState, String> {
+    for notation in moves {
+        let (from, m) = state
+            .parse_coordinate_notation(notation)
+            .map_err(|e| e.to_string())?;
+        state = state.apply(from, m).map_err(|e| e.to_string())?;
+    }
+    Ok(state)
+}
+
+/// How long to think with `remaining` time left on the clock.
+pub(super) fn allot_time(
+    remaining: Duration,
+    increment: Duration,
+    moves_to_go: Option<u32>,
+) -> Duration {
+    let moves = moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+    let time = remaining / moves + increment * 3 / 4;
+    time.min(remaining.saturating_sub(MOVE_OVERHEAD))
+        .max(Duration::from_millis(1))
+}
+
+fn print_info(output: &Output, state: &GameState, info: &SearchInfo) {
+    let score = match info.mate_in() {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", info.score),
+    };
+    let nps = info.nodes as u128 * 1000 / info.time.as_millis().max(1);
+    output.line(format!(
+        "info depth {} score {} nodes {} nps {} time {} hashfull {} pv {}",
+        info.depth,
+        score,
+        info.nodes,
+        nps,
+        info.time.as_millis(),
+        info.hashfull,
+        pv_notation(state, &info.pv).join(" ")
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::eval::Weights;
+    use crate::protocol::uci::{allot_time, Uci};
+    use crate::protocol::{Captured, Output};
+    use std::time::Duration;
+
+    fn uci() -> (Uci, Captured) {
+        let (output, captured) = Output::captured();
+        (Uci::new(Weights::default(), output), captured)
+    }
+
+    #[test]
+    fn handshake() {
+        let (mut uci, captured) = uci();
+        uci.handle("uci");
+        let replies = captured.take();
+        assert_eq!(replies[0], "id name Voideds Chess");
+        assert!(replies
+            .iter()
+            .any(|line| line.starts_with("option name Hash ")));
+        assert_eq!(replies.last().unwrap(), "uciok");
+        uci.handle("isready");
+        assert_eq!(captured.take(), ["readyok"]);
+    }
+
+    #[test]
+    fn position() {
+        let (mut uci, captured) = uci();
+        uci.handle("position startpos moves e2e4 e7e5 g1f3");
+        assert_eq!(
+            uci.position.to_fen(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+        assert_eq!(uci.position.history().len(), 3);
+
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+        uci.handle(&format!("position fen {}", fen));
+        assert_eq!(uci.position.to_fen(), fen);
+        uci.handle(&format!("position fen {} moves e2e4 e8d7", fen));
+        assert_eq!(uci.position.to_fen(), "8/3k4/8/8/4P3/8/8/4K3 w - - 1 2");
+        assert!(captured.take().is_empty());
+
+        // a position that cannot be set up leaves the last one alone
+        let last = uci.position.to_fen();
+        uci.handle("position startpos moves e2e5");
+        uci.handle("position fen 8/8/8/8/8/8/8/8 w - - 0 1");
+        uci.handle("position");
+        assert_eq!(captured.take().len(), 3);
+        assert_eq!(uci.position.to_fen(), last);
+    }
+
+    #[test]
+    fn go_depth() {
+        let (mut uci, captured) = uci();
+        uci.handle("position fen 6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1");
+        uci.handle("go depth 3");
+        uci.engine();
+        let replies = captured.take();
+        assert!(replies[0].starts_with("info depth 1 score "));
+        assert!(replies.iter().any(|line| line.contains(" score mate 1 ")));
+        assert_eq!(replies.last().unwrap(), "bestmove a1a8");
+    }
+
+    #[test]
+    fn stop() {
+        let (mut uci, captured) = uci();
+        uci.handle("position startpos");
+        uci.handle("go infinite");
+        std::thread::sleep(Duration::from_millis(20));
+        // an infinite search only answers once stopped
+        assert!(!captured
+            .take()
+            .iter()
+            .any(|line| line.starts_with("bestmove")));
+        uci.handle("stop");
+        let replies = captured.take();
+        assert!(replies.last().unwrap().starts_with("bestmove "));
+        assert_ne!(replies.last().unwrap(), "bestmove 0000");
+    }
+
+    #[test]
+    fn options() {
+        let (mut uci, captured) = uci();
+        uci.handle("setoption name Hash value 1");
+        uci.handle("setoption name Clear Hash");
+        assert!(captured.take().is_empty());
+        uci.handle("setoption name Hash value lots");
+        uci.handle("setoption name Ponder value true");
+        assert_eq!(
+            captured.take(),
+            [
+                "info string invalid hash size",
+                "info string unknown option 'ponder'"
+            ]
+        );
+
+        // the smaller table still searches
+        uci.handle("position startpos");
+        uci.handle("go depth 2");
+        uci.engine();
+        assert!(captured.take().last().unwrap().starts_with("bestmove "));
+    }
+
+    #[test]
+    fn time_allotment() {
+        let (mut uci, _) = uci();
+        let movetime = |uci: &Uci, go: &str| uci.go_limits(go.split_whitespace()).0.movetime;
+        let secs = Duration::from_secs;
+
+        uci.handle("position startpos");
+        let clocks = "wtime 60000 btime 30000 winc 1000 binc 0";
+        assert_eq!(
+            movetime(&uci, clocks),
+            Some(allot_time(secs(60), secs(1), None))
+        );
+        assert_eq!(
+            movetime(&uci, &format!("{} movestogo 10", clocks)),
+            Some(secs(6) + Duration::from_millis(750))
+        );
+        // the clock of the side to move counts
+        uci.handle("position startpos moves e2e4");
+        assert_eq!(
+            movetime(&uci, &format!("{} movestogo 10", clocks)),
+            Some(secs(3))
+        );
+        // never more than is left on the clock
+        assert_eq!(
+            movetime(&uci, "wtime 5000 btime 20 movestogo 1"),
+            Some(Duration::from_millis(1))
+        );
+
+        assert_eq!(
+            movetime(&uci, "movetime 500 btime 30000"),
+            Some(Duration::from_millis(500))
+        );
+        let (limits, infinite) = uci.go_limits("infinite btime 30000".split_whitespace());
+        assert!(infinite);
+        assert_eq!(limits.movetime, None);
+    }
+
+    #[test]
+    fn chess960_castling() {
+        let (mut uci, _) = uci();
+        let fen = "4k3/8/8/8/8/8/8/4K2R w K - 0 1";
+        uci.handle(&format!("position fen {}", fen));
+        let castle = uci.position.parse_san("O-O").unwrap();
+        assert_eq!(
+            uci.position.to_coordinate_notation(castle.0, castle.1),
+            "e1g1"
+        );
+
+        uci.handle("setoption name UCI_Chess960 value true");
+        uci.handle(&format!("position fen {}", fen));
+        assert_eq!(
+            uci.position.to_coordinate_notation(castle.0, castle.1),
+            "e1h1"
+        );
+        // either way of writing it is understood
+        uci.handle(&format!("position fen {} moves e1g1", fen));
+        assert_eq!(uci.position.history().len(), 1);
+    }
+}
@@ -0,0 +1,473 @@
+use crate::engine::search::Limits;
+use crate::state::clock::Bonus;
+use crate::state::fen::STARTING_FEN;
+use crate::state::game::{GameState, Move, PlayerColor, Position};
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant};
+
+/// How long an engine may take to answer `uci` and `isready`.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long an engine may take to exit after `quit` before it is killed.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UciError {
+    Spawn(String),
+    Io(String),
+    /// The engine closed its output, it most likely exited.
+    Closed,
+    /// The engine did not send the reply in time.
+    Timeout(&'static str),
+    IllegalMove(String),
+}
+
+impl Display for UciError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UciError::Spawn(e) => write!(f, "could not start the engine: {}", e),
+            UciError::Io(e) => write!(f, "could not talk to the engine: {}", e),
+            UciError::Closed => write!(f, "the engine exited"),
+            UciError::Timeout(reply) => write!(f, "the engine did not send '{}' in time", reply),
+            UciError::IllegalMove(m) => write!(f, "the engine sent the illegal move '{}'", m),
+        }
+    }
+}
+
+impl std::error::Error for UciError {}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Score {
+    Centipawns(i32),
+    /// Full moves until mate, negative if the engine gets mated.
+    Mate(i32),
+}
+
+/// What an engine reported about its search so far, everything it left out is `None`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EngineInfo {
+    pub depth: Option<u32>,
+    pub score: Option<Score>,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+    pub pv: Vec<(Position, Move)>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EngineReply {
+    Info(EngineInfo),
+    /// The end of a search, `None` if the engine found no legal move.
+    BestMove(Option<(Position, Move)>),
+}
+
+/// An engine in another process, spoken to over the Universal Chess Interface.
+pub struct UciClient {
+    child: Child,
+    input: ChildStdin,
+    output: Receiver<String>,
+    name: String,
+    /// The position of the running search.
+    searching: Option<GameState>,
+    stopped: bool,
+    /// Whether the engine has been told to expect Chess960 castling.
+    chess960: bool,
+}
+
+impl UciClient {
+    /// Starts `program` and waits for it to finish the `uci` handshake.
+    pub fn spawn(program: &str, args: &[&str]) -> Result<Self, UciError> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| UciError::Spawn(e.to_string()))?;
+        let input = child.stdin.take().ok_or(UciError::Closed)?;
+        let stdout = child.stdout.take().ok_or(UciError::Closed)?;
+
+        let (sender, output) = channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut client = Self {
+            child,
+            input,
+            output,
+            name: program.to_string(),
+            searching: None,
+            stopped: false,
+            chess960: false,
+        };
+        client.send("uci")?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            let line = client.receive_until(deadline, "uciok")?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                client.name = name.trim().to_string();
+            } else if line.trim() == "uciok" {
+                break;
+            }
+        }
+        client.new_game()?;
+        Ok(client)
+    }
+
+    /// The name the engine introduced itself with.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Tells the engine the next position belongs to another game and waits until it is ready.
+    pub fn new_game(&mut self) -> Result<(), UciError> {
+        self.send("ucinewgame")?;
+        self.send("isready")?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        while self.receive_until(deadline, "readyok")?.trim() != "readyok" {}
+        Ok(())
+    }
+
+    /// Starts a search of `state`. The remaining times on its clock are sent along,
+    /// without a clock `limits` should say when to stop.
+    pub fn go(&mut self, state: &GameState, limits: &Limits) -> Result<(), UciError> {
+        let go = go_command(state, limits, Instant::now());
+        if state.is_chess960() && !self.chess960 {
+            self.send("setoption name UCI_Chess960 value true")?;
+            self.chess960 = true;
+        }
+        self.send(&position_command(state))?;
+        self.send(&go)?;
+        self.searching = Some(state.clone());
+        self.stopped = false;
+        Ok(())
+    }
+
+    /// The position the engine is searching, if it is.
+    pub fn searching(&self) -> Option<&GameState> {
+        self.searching.as_ref()
+    }
+
+    /// Asks the engine to end its search, it still answers with a best move.
+    pub fn stop(&mut self) -> Result<(), UciError> {
+        if self.searching.is_some() && !self.stopped {
+            self.stopped = true;
+            self.send("stop")?;
+        }
+        Ok(())
+    }
+
+    /// The next reply about the running search, without waiting for one.
+    pub fn poll(&mut self) -> Result<Option<EngineReply>, UciError> {
+        loop {
+            let line = match self.output.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err(UciError::Closed),
+            };
+            if let Some(reply) = self.parse_reply(&line)? {
+                return Ok(Some(reply));
+            }
+        }
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), UciError> {
+        writeln!(self.input, "{}", command)
+            .and_then(|_| self.input.flush())
+            .map_err(|e| UciError::Io(e.to_string()))
+    }
+
+    fn receive_until(&self, deadline: Instant, expected: &'static str) -> Result<String, UciError> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        self.output.recv_timeout(timeout).map_err(|e| match e {
+            RecvTimeoutError::Timeout => UciError::Timeout(expected),
+            RecvTimeoutError::Disconnected => UciError::Closed,
+        })
+    }
+
+    /// Reads `info` and `bestmove` lines of the running search, anything else is skipped.
+    fn parse_reply(&mut self, line: &str) -> Result<Option<EngineReply>, UciError> {
+        let Some(state) = &self.searching else {
+            return Ok(None);
+        };
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("info") => {
+                // lines like `info string` or `info currmove` carry nothing read here
+                let info = parse_info(state, tokens);
+                Ok((info != EngineInfo::default()).then_some(EngineReply::Info(info)))
+            }
+            Some("bestmove") => {
+                let best = match tokens.next() {
+                    None | Some("0000") | Some("(none)") => None,
+                    Some(notation) => Some(
+                        state
+                            .parse_coordinate_notation(notation)
+                            .map_err(|_| UciError::IllegalMove(notation.to_string()))?,
+                    ),
+                };
+                self.searching = None;
+                Ok(Some(EngineReply::BestMove(best)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Drop for UciClient {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let deadline = Instant::now() + QUIT_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// The `go` command that searches `state` within `limits`, with the clock as it reads at `now`.
+pub fn go_command(state: &GameState, limits: &Limits, now: Instant) -> String {
+    let mut go = "go".to_string();
+    if let Some(depth) = limits.depth {
+        go.push_str(&format!(" depth {}", depth));
+    }
+    if let Some(nodes) = limits.nodes {
+        go.push_str(&format!(" nodes {}", nodes));
+    }
+    if let Some(movetime) = limits.movetime {
+        go.push_str(&format!(" movetime {}", movetime.as_millis()));
+    }
+    if let Some(clock) = state.clock() {
+        for (color, time, increment) in [
+            (PlayerColor::White, "wtime", "winc"),
+            (PlayerColor::Black, "btime", "binc"),
+        ] {
+            go.push_str(&format!(
+                " {} {}",
+                time,
+                clock.remaining(color, now).as_millis()
+            ));
+            // UCI knows no delays, only increments
+            if let Bonus::Increment(bonus) = clock.bonus(color) {
+                go.push_str(&format!(" {} {}", increment, bonus.as_millis()));
+            }
+        }
+        if let Some(moves) = clock.moves_to_go(state.active_color()) {
+            go.push_str(&format!(" movestogo {}", moves));
+        }
+    }
+    if go == "go" {
+        go.push_str(" infinite");
+    }
+    go
+}
+
+/// The `position` command that sets up `state` with the moves that led to it.
+pub fn position_command(state: &GameState) -> String {
+    let mut command = if state.initial_fen() == STARTING_FEN {
+        "position startpos".to_string()
+    } else {
+        format!("position fen {}", state.initial_fen())
+    };
+    let moves = state.history_in_coordinate_notation();
+    if !moves.is_empty() {
+        command.push_str(" moves ");
+        command.push_str(&moves.join(" "));
+    }
+    command
+}
+
+fn parse_info<'a>(state: &GameState, mut tokens: impl Iterator<Item = &'a str>) -> EngineInfo {
+    let mut info = EngineInfo::default();
+    while let Some(token) = tokens.next() {
+        match token {
+            "depth" => info.depth = tokens.next().and_then(|d| d.parse().ok()),
+            "nodes" => info.nodes = tokens.next().and_then(|n| n.parse().ok()),
+            "time" => {
+                info.time = tokens
+                    .next()
+                    .and_then(|t| t.parse().ok())
+                    .map(Duration::from_millis)
+            }
+            "score" => {
+                let kind = tokens.next();
+                let value = tokens.next().and_then(|v| v.parse().ok());
+                info.score = match kind {
+                    Some("cp") => value.map(Score::Centipawns),
+                    Some("mate") => value.map(Score::Mate),
+                    _ => None,
+                };
+            }
+            "pv" => {
+                let mut line = state.clone();
+                for notation in tokens.by_ref() {
+                    let Ok((from, m)) = line.parse_coordinate_notation(notation) else {
+                        break;
+                    };
+                    info.pv.push((from, m));
+                    line = line.apply(from, m).unwrap_or(line);
+                }
+            }
+            // the rest of the line is free text
+            "string" => break,
+            _ => {}
+        }
+    }
+    info
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::engine::search::Limits;
+    use crate::protocol::uci_client::{
+        go_command, position_command, EngineReply, Score, UciClient, UciError,
+    };
+    use crate::state::clock::TimeControl;
+    use crate::state::game::{GameState, Move, Position};
+    use std::time::{Duration, Instant};
+
+    /// A stand-in engine that always wants to play the first move after `go` it was started with.
+    const STAND_IN: &str = r#"
+        while read -r command _; do
+            case "$command" in
+                uci) echo "id name Stand-in"; echo "option name Hash type spin default 1 min 1 max 1"; echo uciok ;;
+                isready) echo readyok ;;
+                go) echo "info depth 1 score cp 13 nodes 20 time 5 pv $1 $2"; echo "info string thinking hard"; echo "bestmove $1" ;;
+                quit) exit 0 ;;
+            esac
+        done
+    "#;
+
+    fn stand_in(moves: &[&str]) -> Result<UciClient, UciError> {
+        let mut args = vec!["-c", STAND_IN, "stand-in"];
+        args.extend_from_slice(moves);
+        UciClient::spawn("sh", &args)
+    }
+
+    fn next_reply(client: &mut UciClient) -> Result<EngineReply, UciError> {
+        for _ in 0..5000 {
+            if let Some(reply) = client.poll()? {
+                return Ok(reply);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        Err(UciError::Timeout("bestmove"))
+    }
+
+    #[test]
+    fn handshake() {
+        let client = stand_in(&["e2e4"]).unwrap();
+        assert_eq!(client.name(), "Stand-in");
+        assert!(matches!(
+            UciClient::spawn("./no-such-engine", &[]),
+            Err(UciError::Spawn(_))
+        ));
+    }
+
+    #[test]
+    fn position_commands() {
+        let state = GameState::new();
+        assert_eq!(position_command(&state), "position startpos");
+
+        let e4 = state.parse_san("e4").unwrap();
+        let state = state.apply(e4.0, e4.1).unwrap();
+        let c5 = state.parse_san("c5").unwrap();
+        let state = state.apply(c5.0, c5.1).unwrap();
+        assert_eq!(
+            position_command(&state),
+            "position startpos moves e2e4 c7c5"
+        );
+
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        let state = GameState::from_fen(fen).unwrap();
+        let castle = state.parse_san("O-O-O").unwrap();
+        let state = state.apply(castle.0, castle.1).unwrap();
+        assert_eq!(
+            position_command(&state),
+            format!("position fen {} moves e1c1", fen)
+        );
+    }
+
+    #[test]
+    fn go_commands() {
+        let now = Instant::now();
+        let state = GameState::new();
+        assert_eq!(go_command(&state, &Limits::default(), now), "go infinite");
+        let limits = Limits {
+            depth: Some(5),
+            ..Limits::default()
+        };
+        assert_eq!(go_command(&state, &limits, now), "go depth 5");
+
+        let control = TimeControl::parse("40/300+5:60d2").unwrap();
+        let state = state.with_clock(control);
+        assert_eq!(
+            go_command(&state, &Limits::default(), now),
+            "go wtime 300000 winc 5000 btime 300000 binc 5000 movestogo 40"
+        );
+
+        // the delay of the last stage cannot be told
+        let control = TimeControl::parse("60d2").unwrap();
+        let state = GameState::new().with_clock(control);
+        assert_eq!(
+            go_command(&state, &Limits::default(), now),
+            "go wtime 60000 btime 60000"
+        );
+    }
+
+    #[test]
+    fn search_replies() {
+        let mut client = stand_in(&["g1f3", "g8f6"]).unwrap();
+        let limits = Limits {
+            movetime: Some(Duration::from_millis(10)),
+            ..Limits::default()
+        };
+        client.go(&GameState::new(), &limits).unwrap();
+        assert!(client.searching().is_some());
+
+        let info = match next_reply(&mut client).unwrap() {
+            EngineReply::Info(info) => info,
+            reply => panic!("expected info, got {:?}", reply),
+        };
+        assert_eq!(info.depth, Some(1));
+        assert_eq!(info.score, Some(Score::Centipawns(13)));
+        assert_eq!(info.nodes, Some(20));
+        assert_eq!(
+            info.pv,
+            vec![
+                (Position(6, 7), Move::Move(Position(5, 5))),
+                (Position(6, 0), Move::Move(Position(5, 2))),
+            ]
+        );
+
+        // the info string is skipped
+        assert_eq!(
+            next_reply(&mut client),
+            Ok(EngineReply::BestMove(Some((
+                Position(6, 7),
+                Move::Move(Position(5, 5))
+            ))))
+        );
+        assert!(client.searching().is_none());
+    }
+
+    #[test]
+    fn illegal_best_move() {
+        let mut client = stand_in(&["e2e5"]).unwrap();
+        client.go(&GameState::new(), &Limits::default()).unwrap();
+        let mut reply = next_reply(&mut client);
+        while let Ok(EngineReply::Info(_)) = reply {
+            reply = next_reply(&mut client);
+        }
+        assert_eq!(reply, Err(UciError::IllegalMove("e2e5".to_string())));
+    }
+}
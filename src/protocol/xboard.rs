@@ -0,0 +1,574 @@
+use crate::engine::eval::{TaperedEval, Weights};
+use crate::engine::search::{Engine, Limits, SearchInfo};
+use crate::protocol::uci::{allot_time, pv_notation};
+use crate::protocol::{Output, ENGINE_NAME};
+use crate::state::game::{GamePhase, GameState, Move, PlayerColor, Position};
+use crate::state::pgn::{result_token, termination};
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How long to think per move when the GUI set no time control at all.
+const DEFAULT_MOVETIME: Duration = Duration::from_secs(5);
+/// XBoard reports mates as scores beyond this.
+const XBOARD_MATE: i32 = 100_000;
+
+enum Event {
+    Command(String),
+    /// The search started as number `id` finished, for the position with the given key
+    /// and ply count.
+    Searched {
+        id: u64,
+        key: u64,
+        plies: usize,
+        best: Option<(Position, Move)>,
+    },
+}
+
+/// The `level` command: `moves` per period (0 for the whole game) in `base` time plus `increment`.
+#[derive(Copy, Clone, Debug)]
+struct Level {
+    moves: u32,
+    base: Duration,
+    increment: Duration,
+}
+
+/// Speaks the Chess Engine Communication Protocol (XBoard/WinBoard) on stdin and stdout.
+pub fn run(weights: Weights) -> Result<(), String> {
+    let (sender, events) = channel();
+    let commands = sender.clone();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            if commands.send(Event::Command(line)).is_err() {
+                break;
+            }
+        }
+        // the GUI went away
+        let _ = commands.send(Event::Command("quit".into()));
+    });
+
+    let mut xboard = XBoard::new(weights, sender, Output::stdout());
+    while let Ok(event) = events.recv() {
+        match event {
+            Event::Command(line) => {
+                if !xboard.handle(&line) {
+                    break;
+                }
+            }
+            Event::Searched {
+                id,
+                key,
+                plies,
+                best,
+            } => xboard.searched(id, key, plies, best),
+        }
+        xboard.think();
+    }
+    xboard.stop_search();
+    Ok(())
+}
+
+struct XBoard {
+    weights: Weights,
+    state: GameState,
+    /// `None` while it thinks about its move, `search` hands it back once the move is sent.
+    engine: Option<Engine>,
+    search: Option<JoinHandle<Engine>>,
+    /// Counts the searches started, an older one finishing late is ignored.
+    search_id: u64,
+    stop: Arc<AtomicBool>,
+    events: Sender<Event>,
+    output: Output,
+    /// The side the engine plays, `None` in force mode.
+    engine_color: Option<PlayerColor>,
+    post: bool,
+    level: Option<Level>,
+    /// Set by `st`, a fixed time for every move.
+    movetime: Option<Duration>,
+    /// Set by `sd`.
+    depth: Option<u32>,
+    /// The engine's clock as last told by `time`.
+    time_left: Option<Duration>,
+}
+
+impl XBoard {
+    fn new(weights: Weights, events: Sender<Event>, output: Output) -> Self {
+        let engine = Engine::with_evaluator(Box::new(TaperedEval::new(weights.clone())));
+        Self {
+            weights,
+            state: GameState::new(),
+            stop: engine.stop_handle(),
+            engine: Some(engine),
+            search: None,
+            search_id: 0,
+            events,
+            output,
+            engine_color: Some(PlayerColor::Black),
+            post: false,
+            level: None,
+            movetime: None,
+            depth: None,
+            time_left: None,
+        }
+    }
+
+    /// Acts on one XBoard command, the engine's own move comes later from [XBoard::think].
+    /// Returns false on `quit`.
+    fn handle(&mut self, line: &str) -> bool {
+        let (command, arguments) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let arguments = arguments.trim();
+        match command {
+            "xboard" | "" => {}
+            "protover" => self.output.line(format!(
+                "feature myname=\"{}\" ping=1 setboard=1 usermove=1 time=1 draw=0 \
+                 sigint=0 sigterm=0 reuse=1 analyze=0 colors=0 done=1",
+                ENGINE_NAME
+            )),
+            "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "name" | "otim" => {
+            }
+            "ping" => self.output.line(format!("pong {}", arguments)),
+            "new" => {
+                self.stop_search();
+                self.state = GameState::new();
+                self.engine_color = Some(PlayerColor::Black);
+                self.depth = None;
+                self.engine().clear();
+            }
+            "setboard" => match GameState::from_fen(arguments) {
+                Ok(state) => {
+                    self.stop_search();
+                    self.state = state;
+                }
+                Err(e) => self
+                    .output
+                    .line(format!("tellusererror Illegal position: {}", e)),
+            },
+            "force" => {
+                self.stop_search();
+                self.engine_color = None;
+            }
+            "go" => {
+                self.stop_search();
+                self.engine_color = Some(self.state.active_color());
+            }
+            // a search stopped early still plays its best move
+            "?" => self.stop_search(),
+            "usermove" => self.user_move(arguments),
+            "undo" => {
+                self.stop_search();
+                self.state = self.state.clone().undo();
+            }
+            "remove" => {
+                self.stop_search();
+                self.state = self.state.clone().undo().undo();
+            }
+            "level" => match parse_level(arguments) {
+                Some(level) => {
+                    self.level = Some(level);
+                    self.movetime = None;
+                }
+                None => self
+                    .output
+                    .line(format!("Error (bad level): {}", arguments)),
+            },
+            "st" => match arguments.parse::<f64>() {
+                Ok(seconds) if seconds > 0.0 => {
+                    self.movetime = Some(Duration::from_secs_f64(seconds))
+                }
+                _ => self.output.line(format!("Error (bad time): {}", arguments)),
+            },
+            "sd" => match arguments.parse::<u32>() {
+                Ok(depth) => self.depth = Some(depth),
+                Err(_) => self
+                    .output
+                    .line(format!("Error (bad depth): {}", arguments)),
+            },
+            "time" => match arguments.parse::<u64>() {
+                // centiseconds
+                Ok(time) => self.time_left = Some(Duration::from_millis(time * 10)),
+                Err(_) => self.output.line(format!("Error (bad time): {}", arguments)),
+            },
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "result" => {
+                self.stop_search();
+                self.engine_color = None;
+            }
+            "quit" => return false,
+            // without usermove=1 moves come on their own
+            _ if self.state.parse_coordinate_notation(command).is_ok() => self.user_move(command),
+            _ => self
+                .output
+                .line(format!("Error (unknown command): {}", command)),
+        }
+        true
+    }
+
+    fn user_move(&mut self, notation: &str) {
+        let played = match self.state.phase() {
+            GamePhase::Turn(_) => self
+                .state
+                .parse_coordinate_notation(notation)
+                .ok()
+                .and_then(|(from, m)| self.state.apply(from, m).ok()),
+            _ => None,
+        };
+        match played {
+            Some(state) => {
+                self.stop_search();
+                self.state = state;
+                self.announce_result();
+            }
+            None => self.output.line(format!("Illegal move: {}", notation)),
+        }
+    }
+
+    /// The engine, waiting for a running search to finish first.
+    fn engine(&mut self) -> &mut Engine {
+        if let Some(search) = self.search.take() {
+            self.engine = search.join().ok();
+        }
+        // only a search that panicked loses the engine
+        let weights = &self.weights;
+        let stop = &mut self.stop;
+        self.engine.get_or_insert_with(|| {
+            let engine = Engine::with_evaluator(Box::new(TaperedEval::new(weights.clone())));
+            *stop = engine.stop_handle();
+            engine
+        })
+    }
+
+    /// Ends a running search, its move is still played unless the position changed since.
+    fn stop_search(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.engine();
+    }
+
+    /// Starts a search if it is the engine's move and it is not already thinking.
+    fn think(&mut self) {
+        if !matches!(self.state.phase(), GamePhase::Turn(player) if Some(player) == self.engine_color)
+        {
+            return;
+        }
+        if self.search.is_some() {
+            return;
+        }
+        self.engine();
+        let Some(mut engine) = self.engine.take() else {
+            return;
+        };
+
+        let limits = self.limits();
+        let state = self.state.clone();
+        let post = self.post;
+        let events = self.events.clone();
+        let output = self.output.clone();
+        self.search_id += 1;
+        let id = self.search_id;
+        self.stop.store(false, Ordering::Relaxed);
+        self.search = Some(std::thread::spawn(move || {
+            let best = engine.search(&state, &limits, |info| {
+                if post {
+                    print_thinking(&output, &state, info);
+                }
+            });
+            let _ = events.send(Event::Searched {
+                id,
+                key: state.hash(),
+                plies: state.history().len(),
+                best,
+            });
+            engine
+        }));
+    }
+
+    fn limits(&self) -> Limits {
+        let mut limits = Limits {
+            depth: self.depth,
+            ..Limits::default()
+        };
+        let remaining = self
+            .time_left
+            .or_else(|| self.level.map(|level| level.base));
+        limits.movetime = match (self.movetime, self.level, remaining) {
+            (Some(movetime), _, _) => Some(movetime),
+            (None, Some(level), Some(remaining)) => {
+                // the moves left until the time control, counting this one
+                let moves_to_go = (level.moves > 0).then(|| {
+                    let played = (self.state.history().len() / 2) as u32;
+                    level.moves - played % level.moves
+                });
+                Some(allot_time(remaining, level.increment, moves_to_go))
+            }
+            (None, None, Some(remaining)) => Some(allot_time(remaining, Duration::ZERO, None)),
+            // a fixed depth alone is enough to stop
+            (None, _, None) if self.depth.is_some() => None,
+            (None, _, None) => Some(DEFAULT_MOVETIME),
+        };
+        limits
+    }
+
+    fn searched(&mut self, id: u64, key: u64, plies: usize, best: Option<(Position, Move)>) {
+        if id != self.search_id {
+            return;
+        }
+        self.engine();
+        let unchanged = self.state.hash() == key && self.state.history().len() == plies;
+        let engine_to_move = matches!(self.state.phase(), GamePhase::Turn(player) if Some(player) == self.engine_color);
+        if let (Some((from, m)), true) = (best, unchanged && engine_to_move) {
+            if let Ok(next) = self.state.apply(from, m) {
+                let notation = self.state.to_coordinate_notation(from, m);
+                self.output.line(format!("move {}", notation));
+                self.state = next;
+                self.announce_result();
+            }
+        }
+    }
+
+    /// Tells the GUI when the last move ended the game.
+    fn announce_result(&self) {
+        let phase = self.state.phase();
+        if let Some(termination) = termination(phase) {
+            self.output
+                .line(format!("{} {{{}}}", result_token(phase), termination));
+        }
+    }
+}
+
+/// Reads `level MPS BASE INC`, the base time in minutes or `minutes:seconds`.
+fn parse_level(arguments: &str) -> Option<Level> {
+    let mut tokens = arguments.split_whitespace();
+    let moves = tokens.next()?.parse::<u32>().ok()?;
+    let base = tokens.next()?;
+    let base = match base.split_once(':') {
+        Some((minutes, seconds)) => {
+            minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?
+        }
+        None => base.parse::<u64>().ok()? * 60,
+    };
+    let increment = tokens.next()?.parse::<f64>().ok()?;
+    if increment < 0.0 {
+        return None;
+    }
+    Some(Level {
+        moves,
+        base: Duration::from_secs(base),
+        increment: Duration::from_secs_f64(increment),
+    })
+}
+
+/// Thinking output as enabled by `post`: depth, score, time in centiseconds, nodes and the line.
+fn print_thinking(output: &Output, state: &GameState, info: &SearchInfo) {
+    let score = match info.mate_in() {
+        Some(moves) if moves > 0 => XBOARD_MATE + moves,
+        Some(moves) => -XBOARD_MATE + moves,
+        None => info.score,
+    };
+    output.line(format!(
+        "{} {} {} {} {}",
+        info.depth,
+        score,
+        info.time.as_millis() / 10,
+        info.nodes,
+        pv_notation(state, &info.pv).join(" ")
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::eval::Weights;
+    use crate::engine::tt::TableStats;
+    use crate::protocol::uci::allot_time;
+    use crate::protocol::xboard::{Event, XBoard};
+    use crate::protocol::{Captured, Output};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::time::Duration;
+
+    fn xboard() -> (XBoard, Receiver<Event>, Captured) {
+        let (sender, events) = channel();
+        let (output, captured) = Output::captured();
+        (
+            XBoard::new(Weights::default(), sender, output),
+            events,
+            captured,
+        )
+    }
+
+    /// Lets the engine move if it is its turn, like the event loop does after every command.
+    fn think(xboard: &mut XBoard, events: &Receiver<Event>) {
+        xboard.think();
+        if xboard.search.is_none() {
+            return;
+        }
+        match events.recv_timeout(Duration::from_secs(10)) {
+            Ok(Event::Searched {
+                id,
+                key,
+                plies,
+                best,
+            }) => xboard.searched(id, key, plies, best),
+            _ => panic!("the search did not finish"),
+        }
+    }
+
+    #[test]
+    fn handshake() {
+        let (mut xboard, _, captured) = xboard();
+        xboard.handle("xboard");
+        xboard.handle("protover 2");
+        let features = captured.take();
+        assert_eq!(features.len(), 1);
+        assert!(features[0].starts_with("feature myname=\"Voideds Chess\" "));
+        assert!(features[0].contains(" usermove=1 "));
+        assert!(features[0].ends_with(" done=1"));
+
+        xboard.handle("accepted usermove");
+        xboard.handle("ping 7");
+        xboard.handle("frobnicate");
+        assert_eq!(
+            captured.take(),
+            ["pong 7", "Error (unknown command): frobnicate"]
+        );
+    }
+
+    #[test]
+    fn engine_answers_moves() {
+        let (mut xboard, events, captured) = xboard();
+        xboard.handle("new");
+        xboard.handle("sd 1");
+        xboard.handle("usermove e2e4");
+        think(&mut xboard, &events);
+        assert_eq!(xboard.state.history().len(), 2);
+        let replies = captured.take();
+        assert_eq!(replies.len(), 1);
+        assert!(replies[0].starts_with("move "), "{:?}", replies);
+
+        // without usermove=1 a move comes on its own
+        xboard.handle("d2d4");
+        think(&mut xboard, &events);
+        assert_eq!(xboard.state.history().len(), 4);
+
+        xboard.handle("usermove e2e5");
+        assert_eq!(captured.take().last().unwrap(), "Illegal move: e2e5");
+        assert_eq!(xboard.state.history().len(), 4);
+    }
+
+    #[test]
+    fn force_and_go() {
+        let (mut xboard, events, captured) = xboard();
+        xboard.handle("new");
+        xboard.handle("force");
+        xboard.handle("sd 1");
+        for m in ["e2e4", "e7e5", "g1f3"] {
+            xboard.handle(&format!("usermove {}", m));
+            think(&mut xboard, &events);
+        }
+        assert_eq!(xboard.state.history().len(), 3);
+        assert!(captured.take().is_empty());
+
+        xboard.handle("undo");
+        assert_eq!(xboard.state.history().len(), 2);
+        xboard.handle("remove");
+        assert!(xboard.state.history().is_empty());
+
+        // the engine takes the side to move, white here
+        xboard.handle("go");
+        think(&mut xboard, &events);
+        assert_eq!(xboard.state.history().len(), 1);
+        assert!(captured.take()[0].starts_with("move "));
+
+        // after the result nothing is played any more
+        xboard.handle("usermove e7e5");
+        xboard.handle("result 0-1 {White resigns}");
+        think(&mut xboard, &events);
+        assert_eq!(xboard.state.history().len(), 2);
+    }
+
+    #[test]
+    fn setboard() {
+        let (mut xboard, _, captured) = xboard();
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 b - - 0 1";
+        xboard.handle(&format!("setboard {}", fen));
+        assert_eq!(xboard.state.to_fen(), fen);
+
+        xboard.handle("setboard 4k3/8/8/8/8/8/8/8 w - - 0 1");
+        let reply = captured.take();
+        assert!(reply[0].starts_with("tellusererror Illegal position: "));
+        assert_eq!(xboard.state.to_fen(), fen);
+    }
+
+    #[test]
+    fn time_controls() {
+        let (mut xboard, _, captured) = xboard();
+        let movetime = |xboard: &XBoard| xboard.limits().movetime;
+
+        xboard.handle("level 40 5 0");
+        assert_eq!(
+            movetime(&xboard),
+            Some(allot_time(
+                Duration::from_secs(300),
+                Duration::ZERO,
+                Some(40)
+            ))
+        );
+        // the clock the GUI reports counts, in centiseconds
+        xboard.handle("time 6000");
+        xboard.handle("level 0 2:30 3");
+        assert_eq!(
+            movetime(&xboard),
+            Some(allot_time(
+                Duration::from_secs(60),
+                Duration::from_secs(3),
+                None
+            ))
+        );
+
+        xboard.handle("st 2");
+        assert_eq!(movetime(&xboard), Some(Duration::from_secs(2)));
+        xboard.handle("sd 4");
+        assert_eq!(xboard.limits().depth, Some(4));
+
+        xboard.handle("level 40 x 0");
+        xboard.handle("st -1");
+        xboard.handle("sd deep");
+        assert_eq!(
+            captured.take(),
+            [
+                "Error (bad level): 40 x 0",
+                "Error (bad time): -1",
+                "Error (bad depth): deep"
+            ]
+        );
+    }
+
+    #[test]
+    fn new_forgets_the_running_search() {
+        let (mut xboard, events, captured) = xboard();
+        xboard.handle("new");
+        xboard.handle("usermove e2e4");
+        // thinks for the default time, unless stopped
+        xboard.think();
+        assert!(xboard.engine.is_none());
+
+        xboard.handle("new");
+        let engine = xboard.engine.as_ref().unwrap();
+        assert_eq!(engine.hash_stats(), TableStats::default());
+
+        // the search that was stopped does not move in the new game
+        think(&mut xboard, &events);
+        if let Ok(Event::Searched {
+            id,
+            key,
+            plies,
+            best,
+        }) = events.recv_timeout(Duration::from_secs(1))
+        {
+            xboard.searched(id, key, plies, best);
+        }
+        assert!(xboard.state.history().is_empty());
+        assert!(captured.take().is_empty());
+    }
+}
@@ -0,0 +1,543 @@
+use crate::state::board::PieceKind;
+use crate::state::game::{Piece, PlayerColor};
+use std::sync::OnceLock;
+
+/// One bit per square, bit `n` is the square with [Position::idx](crate::state::game::Position::idx) `n`,
+/// i.e. a8 is bit 0 and h1 is bit 63.
+pub type Bitboard = u64;
+
+pub const FILE_A: Bitboard = 0x0101_0101_0101_0101;
+pub const RANK_8: Bitboard = 0xff;
+pub const RANK_1: Bitboard = RANK_8 << 56;
+pub const LIGHT_SQUARES: Bitboard = 0xaa55_aa55_aa55_aa55;
+/// d4, e4, d5 and e5.
+pub const CENTER: Bitboard = 0x0000_0018_1800_0000;
+
+const ROOK_DIRECTIONS: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+const BISHOP_DIRECTIONS: [(i32, i32); 4] = [(-1, -1), (-1, 1), (1, -1), (1, 1)];
+const KING_MOVEMENT: [(i32, i32); 8] = [
+    (-1, -1),
+    (-1, 1),
+    (1, -1),
+    (1, 1),
+    (0, -1),
+    (0, 1),
+    (-1, 0),
+    (1, 0),
+];
+const KNIGHT_MOVEMENT: [(i32, i32); 8] = [
+    (-1, -2),
+    (-1, 2),
+    (1, -2),
+    (1, 2),
+    (-2, -1),
+    (-2, 1),
+    (2, -1),
+    (2, 1),
+];
+
+pub fn square_bit(square: usize) -> Bitboard {
+    1 << square
+}
+
+/// Removes the lowest set bit and returns its square.
+pub fn pop_square(bitboard: &mut Bitboard) -> usize {
+    let square = bitboard.trailing_zeros() as usize;
+    *bitboard &= *bitboard - 1;
+    square
+}
+
+pub fn squares(mut bitboard: Bitboard) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if bitboard == 0 {
+            None
+        } else {
+            Some(pop_square(&mut bitboard))
+        }
+    })
+}
+
+pub fn color_index(color: PlayerColor) -> usize {
+    match color {
+        PlayerColor::Black => 0,
+        PlayerColor::White => 1,
+    }
+}
+
+pub fn kind_index(kind: PieceKind) -> usize {
+    match kind {
+        PieceKind::Pawn => 0,
+        PieceKind::Rook => 1,
+        PieceKind::Knight => 2,
+        PieceKind::Bishop => 3,
+        PieceKind::King => 4,
+        PieceKind::Queen => 5,
+    }
+}
+
+/// The pieces both as per-kind and per-color bitboards and as a mailbox for square lookups.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Board {
+    pieces: [Bitboard; 6],
+    colors: [Bitboard; 2],
+    mailbox: [Option<Piece>; 64],
+}
+
+impl Board {
+    pub fn empty() -> Self {
+        Self {
+            pieces: [0; 6],
+            colors: [0; 2],
+            mailbox: [None; 64],
+        }
+    }
+
+    pub fn from_mailbox(mailbox: [Option<Piece>; 64]) -> Self {
+        let mut board = Self::empty();
+        for (square, piece) in mailbox.into_iter().enumerate() {
+            if let Some(piece) = piece {
+                board.put(square, piece);
+            }
+        }
+        board
+    }
+
+    pub fn get(&self, square: usize) -> Option<&Piece> {
+        self.mailbox[square].as_ref()
+    }
+
+    pub fn put(&mut self, square: usize, piece: Piece) {
+        self.remove(square);
+        let bit = square_bit(square);
+        self.pieces[kind_index(piece.kind)] |= bit;
+        self.colors[color_index(piece.color)] |= bit;
+        self.mailbox[square] = Some(piece);
+    }
+
+    pub fn remove(&mut self, square: usize) -> Option<Piece> {
+        let piece = self.mailbox[square].take()?;
+        let bit = square_bit(square);
+        self.pieces[kind_index(piece.kind)] &= !bit;
+        self.colors[color_index(piece.color)] &= !bit;
+        Some(piece)
+    }
+
+    pub fn occupied(&self) -> Bitboard {
+        self.colors[0] | self.colors[1]
+    }
+
+    pub fn color(&self, color: PlayerColor) -> Bitboard {
+        self.colors[color_index(color)]
+    }
+
+    pub fn kind(&self, kind: PieceKind) -> Bitboard {
+        self.pieces[kind_index(kind)]
+    }
+
+    pub fn pieces(&self, kind: PieceKind, color: PlayerColor) -> Bitboard {
+        self.kind(kind) & self.color(color)
+    }
+
+    /// Every piece of `by` attacking `square`, given the occupancy `occupied`.
+    pub fn attackers(&self, square: usize, by: PlayerColor, occupied: Bitboard) -> Bitboard {
+        let queens = self.kind(PieceKind::Queen);
+        let attackers = (pawn_attacks(by.opponent(), square) & self.kind(PieceKind::Pawn))
+            | (knight_attacks(square) & self.kind(PieceKind::Knight))
+            | (king_attacks(square) & self.kind(PieceKind::King))
+            | (rook_attacks(square, occupied) & (self.kind(PieceKind::Rook) | queens))
+            | (bishop_attacks(square, occupied) & (self.kind(PieceKind::Bishop) | queens));
+        attackers & self.color(by) & occupied
+    }
+}
+
+/// A magic multiplier hashing the relevant blockers of a square into its slice of the attack table.
+struct Magic {
+    mask: Bitboard,
+    magic: u64,
+    shift: u32,
+    offset: usize,
+}
+
+impl Magic {
+    fn index(&self, occupied: Bitboard) -> usize {
+        self.offset + ((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+struct AttackTables {
+    knight: [Bitboard; 64],
+    king: [Bitboard; 64],
+    pawn: [[Bitboard; 64]; 2],
+    rook_magics: Vec<Magic>,
+    bishop_magics: Vec<Magic>,
+    sliding: Vec<Bitboard>,
+}
+
+fn tables() -> &'static AttackTables {
+    static TABLES: OnceLock<AttackTables> = OnceLock::new();
+    TABLES.get_or_init(AttackTables::new)
+}
+
+fn step(square: usize, (dx, dy): (i32, i32)) -> Option<usize> {
+    let x = (square % 8) as i32 + dx;
+    let y = (square / 8) as i32 + dy;
+    if (0..8).contains(&x) && (0..8).contains(&y) {
+        Some((x + y * 8) as usize)
+    } else {
+        None
+    }
+}
+
+fn leaper_attacks(square: usize, steps: &[(i32, i32)]) -> Bitboard {
+    steps
+        .iter()
+        .filter_map(|s| step(square, *s))
+        .fold(0, |attacks, target| attacks | square_bit(target))
+}
+
+/// Walks every ray until it leaves the board or hits a blocker, the slow reference for the magics.
+fn slider_attacks(square: usize, directions: &[(i32, i32)], occupied: Bitboard) -> Bitboard {
+    let mut attacks = 0;
+    for direction in directions {
+        let mut current = square;
+        while let Some(target) = step(current, *direction) {
+            attacks |= square_bit(target);
+            if occupied & square_bit(target) != 0 {
+                break;
+            }
+            current = target;
+        }
+    }
+    attacks
+}
+
+/// The squares whose occupancy matters for a slider, the board edge never blocks anything.
+fn relevant_blockers(square: usize, directions: &[(i32, i32)]) -> Bitboard {
+    let mut mask = 0;
+    for direction in directions {
+        let mut current = square;
+        while let Some(target) = step(current, *direction) {
+            if step(target, *direction).is_none() {
+                break;
+            }
+            mask |= square_bit(target);
+            current = target;
+        }
+    }
+    mask
+}
+
+impl AttackTables {
+    fn new() -> Self {
+        let mut knight = [0; 64];
+        let mut king = [0; 64];
+        let mut pawn = [[0; 64]; 2];
+        for square in 0..64 {
+            knight[square] = leaper_attacks(square, &KNIGHT_MOVEMENT);
+            king[square] = leaper_attacks(square, &KING_MOVEMENT);
+            pawn[color_index(PlayerColor::Black)][square] =
+                leaper_attacks(square, &[(-1, 1), (1, 1)]);
+            pawn[color_index(PlayerColor::White)][square] =
+                leaper_attacks(square, &[(-1, -1), (1, -1)]);
+        }
+
+        let mut sliding = vec![];
+        let rook_magics = Self::fill_magics(&ROOK_MAGICS, &ROOK_DIRECTIONS, &mut sliding);
+        let bishop_magics = Self::fill_magics(&BISHOP_MAGICS, &BISHOP_DIRECTIONS, &mut sliding);
+
+        Self {
+            knight,
+            king,
+            pawn,
+            rook_magics,
+            bishop_magics,
+            sliding,
+        }
+    }
+
+    fn fill_magics(
+        magics: &[u64; 64],
+        directions: &[(i32, i32)],
+        sliding: &mut Vec<Bitboard>,
+    ) -> Vec<Magic> {
+        (0..64)
+            .map(|square| {
+                let mask = relevant_blockers(square, directions);
+                let bits = mask.count_ones();
+                let magic = Magic {
+                    mask,
+                    magic: magics[square],
+                    shift: 64 - bits,
+                    offset: sliding.len(),
+                };
+                sliding.resize(sliding.len() + (1 << bits), 0);
+
+                // enumerate every subset of the mask (carry-rippler)
+                let mut blockers: Bitboard = 0;
+                loop {
+                    let index = magic.index(blockers);
+                    let attacks = slider_attacks(square, directions, blockers);
+                    debug_assert!(sliding[index] == 0 || sliding[index] == attacks);
+                    sliding[index] = attacks;
+
+                    blockers = blockers.wrapping_sub(mask) & mask;
+                    if blockers == 0 {
+                        break;
+                    }
+                }
+                magic
+            })
+            .collect()
+    }
+}
+
+pub fn knight_attacks(square: usize) -> Bitboard {
+    tables().knight[square]
+}
+
+pub fn king_attacks(square: usize) -> Bitboard {
+    tables().king[square]
+}
+
+/// The squares a pawn of `color` on `square` attacks.
+pub fn pawn_attacks(color: PlayerColor, square: usize) -> Bitboard {
+    tables().pawn[color_index(color)][square]
+}
+
+pub fn rook_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    let tables = tables();
+    tables.sliding[tables.rook_magics[square].index(occupied)]
+}
+
+pub fn bishop_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    let tables = tables();
+    tables.sliding[tables.bishop_magics[square].index(occupied)]
+}
+
+pub fn queen_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    rook_attacks(square, occupied) | bishop_attacks(square, occupied)
+}
+
+pub fn piece_attacks(piece: Piece, square: usize, occupied: Bitboard) -> Bitboard {
+    match piece.kind {
+        PieceKind::Pawn => pawn_attacks(piece.color, square),
+        PieceKind::Rook => rook_attacks(square, occupied),
+        PieceKind::Knight => knight_attacks(square),
+        PieceKind::Bishop => bishop_attacks(square, occupied),
+        PieceKind::King => king_attacks(square),
+        PieceKind::Queen => queen_attacks(square, occupied),
+    }
+}
+
+// found by trial and error with sparse random candidates, valid for the square layout above
+const ROOK_MAGICS: [u64; 64] = [
+    0x2080002080400010,
+    0x00c0002001401000,
+    0x2100110008402002,
+    0x0880080081041000,
+    0x0200020020041008,
+    0x2300040008010012,
+    0x0c00283004008201,
+    0x0180010000407a80,
+    0x0168800080400020,
+    0x0010400040201000,
+    0x1001002001001048,
+    0x1001002408100100,
+    0x0801000408010012,
+    0x4001000209000400,
+    0x08a20004c8020001,
+    0x2002801145002280,
+    0x0080860021004200,
+    0x001000c009402002,
+    0x00b0002004002800,
+    0x100a808010020800,
+    0x8101010008000410,
+    0x0244008002000480,
+    0x0000040010810208,
+    0x2000020000448534,
+    0x4104400480008033,
+    0x0000810100204000,
+    0x0440430900200010,
+    0x4600240900100100,
+    0x0060080080040080,
+    0x0001000300080400,
+    0x0004084400011002,
+    0x0023040200008041,
+    0x0580050043002080,
+    0x0400804002802008,
+    0x0001002001004010,
+    0x1000200901001000,
+    0x4410800801800c00,
+    0xa012003806001004,
+    0x0020100104008802,
+    0x0004808402000041,
+    0x0010400170898000,
+    0x0080500020004004,
+    0x1040408012020020,
+    0x8010040008004040,
+    0x2001080100110004,
+    0x0000020004008080,
+    0x0021010810040002,
+    0x0800008c43020024,
+    0x0000800021005100,
+    0x0070201040008080,
+    0x0000d04282006a00,
+    0x0010014400080240,
+    0x0001080110050100,
+    0x0012000810240600,
+    0x0402000801040200,
+    0x028100108a004100,
+    0x0050800300102045,
+    0x8208210040120882,
+    0x8010600101183441,
+    0x020b000910006045,
+    0x0241001002480005,
+    0x0081000400880241,
+    0x0000009008024124,
+    0x0048122980410402,
+];
+
+const BISHOP_MAGICS: [u64; 64] = [
+    0x0848020822040013,
+    0x8010a40085821200,
+    0x0008008430840822,
+    0x0808048108040000,
+    0x1304042100008104,
+    0x5001012010204023,
+    0x81048801b8200420,
+    0x200a008084012000,
+    0x0040102001042084,
+    0x840a505042428020,
+    0x0000700102202920,
+    0x44101c0c10800002,
+    0x0040040422000000,
+    0x0180020802090202,
+    0x4020020811041202,
+    0x000104308c042000,
+    0x4140661002424400,
+    0x0028012008010460,
+    0x0188062102002a00,
+    0x0014004840102008,
+    0x0105000290400002,
+    0x8001022200410400,
+    0x104a041918013446,
+    0x008a000082008238,
+    0x04a0060008100430,
+    0x0008220008820801,
+    0x2508041208005010,
+    0x4008080200202020,
+    0x2441001013004000,
+    0x0030008060407000,
+    0x4008108000420800,
+    0x0012021050290100,
+    0x0210080482200500,
+    0xcc01112048100480,
+    0x0020402806500440,
+    0x00048e0080580080,
+    0x0040102020020080,
+    0x0028010440080807,
+    0x4601041108008800,
+    0x8040810e04104200,
+    0x901210110400088a,
+    0xa003080212081050,
+    0x00c1004048401004,
+    0x900000a014400800,
+    0x0008021040405401,
+    0x4020008206002090,
+    0x0004190424030100,
+    0x0424008a02026250,
+    0x8004088250900040,
+    0x1c00430088a04200,
+    0x0001020094040001,
+    0x8040210020880061,
+    0x2010040450442032,
+    0x0800840850044001,
+    0x0004040802140004,
+    0x0004080a04222020,
+    0x8088802110022000,
+    0x1081a10416114400,
+    0x0205010a24060820,
+    0x0000000720411080,
+    0x1008000208430400,
+    0x580c026028810840,
+    0x802020441020a110,
+    0x12c0022401020018,
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::state::bitboard::{
+        bishop_attacks, rook_attacks, slider_attacks, square_bit, squares, Board,
+        BISHOP_DIRECTIONS, ROOK_DIRECTIONS,
+    };
+    use crate::state::board::PieceKind;
+    use crate::state::game::{Piece, PlayerColor, Position};
+
+    #[test]
+    fn magics_match_the_ray_walk() {
+        // xorshift, and-ing two draws gives the sparser boards of real games
+        let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for square in 0..64 {
+            for _ in 0..256 {
+                let occupied = random() & random();
+                assert_eq!(
+                    rook_attacks(square, occupied),
+                    slider_attacks(square, &ROOK_DIRECTIONS, occupied)
+                );
+                assert_eq!(
+                    bishop_attacks(square, occupied),
+                    slider_attacks(square, &BISHOP_DIRECTIONS, occupied)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn board() {
+        let a1 = Position(0, 7).idx();
+        let a4 = Position(0, 4).idx();
+        let a8 = Position(0, 0).idx();
+        let rook = Piece {
+            kind: PieceKind::Rook,
+            color: PlayerColor::White,
+        };
+        let queen = Piece {
+            kind: PieceKind::Queen,
+            color: PlayerColor::Black,
+        };
+
+        let mut board = Board::empty();
+        board.put(a1, rook);
+        assert_eq!(board.get(a1), Some(&rook));
+        assert_eq!(
+            board.pieces(PieceKind::Rook, PlayerColor::White),
+            square_bit(a1)
+        );
+        assert_eq!(
+            board.attackers(a8, PlayerColor::White, board.occupied()),
+            square_bit(a1)
+        );
+
+        // a piece put on an occupied square replaces the one standing there
+        board.put(a4, rook);
+        board.put(a1, queen);
+        assert_eq!(board.kind(PieceKind::Rook), square_bit(a4));
+        assert_eq!(board.color(PlayerColor::Black), square_bit(a1));
+        assert_eq!(squares(board.occupied()).collect::<Vec<_>>(), [a4, a1]);
+
+        // the rook on a4 stands between the queen and a8
+        assert_eq!(board.attackers(a8, PlayerColor::Black, board.occupied()), 0);
+        assert_eq!(board.remove(a4), Some(rook));
+        assert_eq!(board.remove(a4), None);
+        assert_eq!(
+            board.attackers(a8, PlayerColor::Black, board.occupied()),
+            square_bit(a1)
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use crate::state::game::GameState;
+
+/// The number of Fischer Random start positions.
+pub const CHESS960_POSITIONS: u16 = 960;
+
+/// The white back rank of Chess960 start position `index` as FEN letters, numbered the usual
+/// (Scharnagl) way in which 518 is the standard start position.
+fn back_rank(index: u16) -> Option<[char; 8]> {
+    if index >= CHESS960_POSITIONS {
+        return None;
+    }
+    let mut rank = [' '; 8];
+    let mut n = index as usize;
+
+    // one bishop on a light and one on a dark square
+    rank[n % 4 * 2 + 1] = 'B';
+    n /= 4;
+    rank[n % 4 * 2] = 'B';
+    n /= 4;
+
+    let mut place = |piece: char, nth_empty: usize| {
+        let file = (0..8)
+            .filter(|file| rank[*file] == ' ')
+            .nth(nth_empty)
+            .expect("There are enough empty squares!");
+        rank[file] = piece;
+    };
+    place('Q', n % 6);
+    n /= 6;
+
+    // the ten ways to put two knights on the five squares left, the second
+    // counted among the squares still empty after placing the first
+    let (first, second) = [
+        (0, 0),
+        (0, 1),
+        (0, 2),
+        (0, 3),
+        (1, 1),
+        (1, 2),
+        (1, 3),
+        (2, 2),
+        (2, 3),
+        (3, 3),
+    ][n];
+    place('N', first);
+    place('N', second);
+
+    // the king between the rooks on the three squares left
+    place('R', 0);
+    place('K', 0);
+    place('R', 0);
+    Some(rank)
+}
+
+impl GameState {
+    /// A game of Fischer Random Chess from start position `index` (0–959).
+    pub fn new_chess960(index: u16) -> Option<GameState> {
+        let white = back_rank(index)?.iter().collect::<String>();
+        let fen = format!(
+            "{}/pppppppp/8/8/8/8/PPPPPPPP/{} w KQkq - 0 1",
+            white.to_lowercase(),
+            white
+        );
+        let state = GameState::from_fen(&fen).expect("Chess960 positions are valid!");
+        Some(state.into_chess960())
+    }
+
+    /// Plays the game as Chess960 from here on, castling being written as the king taking
+    /// its own rook even from the standard start position.
+    pub fn into_chess960(mut self) -> Self {
+        self.chess960 = true;
+        self
+    }
+}
@@ -0,0 +1,361 @@
+use crate::state::bitboard::color_index;
+use crate::state::game::PlayerColor;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// What a player gets back for completing a move.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Bonus {
+    None,
+    /// Fischer increment, added after every move.
+    Increment(Duration),
+    /// Bronstein delay, the time used is given back up to the delay.
+    Bronstein(Duration),
+    /// Simple (US) delay, the clock only starts counting down once the delay has passed.
+    Delay(Duration),
+}
+
+/// One period of a time control, `moves` being `None` for the rest of the game.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Stage {
+    pub moves: Option<u32>,
+    pub time: Duration,
+    pub bonus: Bonus,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TimeControl {
+    stages: Vec<Stage>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TimeControlError {
+    Empty,
+    InvalidStage(String),
+    /// Only the last stage may be open ended.
+    UnreachableStage(String),
+}
+
+impl Display for TimeControlError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimeControlError::Empty => write!(f, "empty time control"),
+            TimeControlError::InvalidStage(stage) => write!(f, "invalid time control '{}'", stage),
+            TimeControlError::UnreachableStage(stage) => {
+                write!(
+                    f,
+                    "time control '{}' follows a stage without a move count",
+                    stage
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for TimeControlError {}
+
+impl TimeControl {
+    /// A control made of several periods, e.g. 40 moves in 90 minutes followed by
+    /// 30 minutes for the rest of the game. A last stage with a move count repeats.
+    pub fn multi_stage(stages: Vec<Stage>) -> Result<Self, TimeControlError> {
+        if stages.is_empty() {
+            return Err(TimeControlError::Empty);
+        }
+        let control = Self { stages };
+        if let Some(stage) = control.stages[..control.stages.len() - 1]
+            .iter()
+            .position(|stage| stage.moves.is_none())
+        {
+            let unreachable = Self {
+                stages: vec![control.stages[stage + 1]],
+            };
+            return Err(TimeControlError::UnreachableStage(unreachable.to_string()));
+        }
+        Ok(control)
+    }
+
+    /// Reads the PGN `TimeControl` tag syntax, all times in seconds: stages are separated
+    /// by `:`, `40/5400` is 40 moves in 90 minutes and `+30` a 30 second increment.
+    /// As an extension `d5` stands for a 5 second simple delay and `b5` for a Bronstein delay,
+    /// so FIDE's 40/90+30 with a 30 second increment reads `40/5400+30:1800+30`.
+    pub fn parse(spec: &str) -> Result<Self, TimeControlError> {
+        let stages = spec
+            .split(':')
+            .map(|stage| Self::parse_stage(stage.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::multi_stage(stages)
+    }
+
+    fn parse_stage(stage: &str) -> Result<Stage, TimeControlError> {
+        let invalid = || TimeControlError::InvalidStage(stage.to_string());
+        let seconds = |s: &str| {
+            s.parse::<u64>()
+                .map(Duration::from_secs)
+                .map_err(|_| invalid())
+        };
+
+        let (moves, rest) = match stage.split_once('/') {
+            Some((moves, rest)) => (Some(moves.parse::<u32>().map_err(|_| invalid())?), rest),
+            None => (None, stage),
+        };
+        if moves == Some(0) {
+            return Err(invalid());
+        }
+
+        let (time, bonus) = match rest.find(['+', 'd', 'b']) {
+            Some(split) => {
+                let delay = seconds(&rest[split + 1..])?;
+                let bonus = match &rest[split..split + 1] {
+                    "+" => Bonus::Increment(delay),
+                    "d" => Bonus::Delay(delay),
+                    _ => Bonus::Bronstein(delay),
+                };
+                (seconds(&rest[..split])?, bonus)
+            }
+            None => (seconds(rest)?, Bonus::None),
+        };
+
+        Ok(Stage { moves, time, bonus })
+    }
+
+    /// The stage a player is in after completing `moves` moves, and the move it started at.
+    fn stage(&self, moves: u32) -> (usize, u32) {
+        let mut played = 0;
+        for (index, stage) in self.stages.iter().enumerate() {
+            match stage.moves {
+                Some(length) if played + length <= moves => played += length,
+                _ => return (index, played),
+            }
+        }
+
+        // a last stage with a move count starts over
+        let last = self.stages.len() - 1;
+        let length = self.stages[last].moves.unwrap_or(1);
+        let repeats = (moves - played) / length;
+        (last, played + repeats * length)
+    }
+}
+
+impl Display for TimeControl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (index, stage) in self.stages.iter().enumerate() {
+            if index > 0 {
+                write!(f, ":")?;
+            }
+            if let Some(moves) = stage.moves {
+                write!(f, "{}/", moves)?;
+            }
+            write!(f, "{}", stage.time.as_secs())?;
+            match stage.bonus {
+                Bonus::None => {}
+                Bonus::Increment(d) => write!(f, "+{}", d.as_secs())?,
+                Bonus::Bronstein(d) => write!(f, "b{}", d.as_secs())?,
+                Bonus::Delay(d) => write!(f, "d{}", d.as_secs())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A chess clock for both players, driven by the times passed into it.
+#[derive(Clone, Debug)]
+pub struct Clock {
+    control: TimeControl,
+    remaining: [Duration; 2],
+    moves: [u32; 2],
+    running: Option<(PlayerColor, Instant)>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        let time = control.stages[0].time;
+        Self {
+            control,
+            remaining: [time; 2],
+            moves: [0; 2],
+            running: None,
+        }
+    }
+
+    pub fn time_control(&self) -> &TimeControl {
+        &self.control
+    }
+
+    /// Starts counting down the time of `color`.
+    pub fn start(&mut self, color: PlayerColor, now: Instant) {
+        self.running = Some((color, now));
+    }
+
+    /// Stops the clock, charging the running side for the time it used.
+    pub fn stop(&mut self, now: Instant) {
+        if let Some((color, _)) = self.running {
+            self.remaining[color_index(color)] = self.remaining(color, now);
+            self.running = None;
+        }
+    }
+
+    /// Ends the turn of `color` and starts the clock of the opponent.
+    pub fn press(&mut self, color: PlayerColor, now: Instant) {
+        let (used, delay) = match self.running {
+            Some((running, started)) if running == color => {
+                (now.saturating_duration_since(started), self.bonus(color))
+            }
+            _ => (Duration::ZERO, Bonus::None),
+        };
+
+        let index = color_index(color);
+        let mut remaining = self.remaining(color, now);
+        if !remaining.is_zero() {
+            remaining += match delay {
+                Bonus::Increment(increment) => increment,
+                Bonus::Bronstein(delay) => used.min(delay),
+                Bonus::None | Bonus::Delay(_) => Duration::ZERO,
+            };
+
+            // reaching the move count of a stage adds the time of the next one
+            let (_, period) = self.control.stage(self.moves[index]);
+            self.moves[index] += 1;
+            let (stage, next_period) = self.control.stage(self.moves[index]);
+            if next_period != period {
+                remaining += self.control.stages[stage].time;
+            }
+        }
+
+        self.remaining[index] = remaining;
+        self.running = Some((color.opponent(), now));
+    }
+
+    /// The time `color` has left at `now`.
+    pub fn remaining(&self, color: PlayerColor, now: Instant) -> Duration {
+        let remaining = self.remaining[color_index(color)];
+        match self.running {
+            Some((running, started)) if running == color => {
+                let mut used = now.saturating_duration_since(started);
+                if let Bonus::Delay(delay) = self.bonus(color) {
+                    used = used.saturating_sub(delay);
+                }
+                remaining.saturating_sub(used)
+            }
+            _ => remaining,
+        }
+    }
+
+    /// The player whose time ran out, if any.
+    pub fn flagged(&self, now: Instant) -> Option<PlayerColor> {
+        [PlayerColor::White, PlayerColor::Black]
+            .into_iter()
+            .find(|color| self.remaining(*color, now).is_zero())
+    }
+
+    /// How many moves `color` has left to reach the next stage, `None` in the last stage
+    /// if it lasts for the rest of the game.
+    pub fn moves_to_go(&self, color: PlayerColor) -> Option<u32> {
+        let moves = self.moves[color_index(color)];
+        let (stage, start) = self.control.stage(moves);
+        self.control.stages[stage]
+            .moves
+            .map(|length| start + length - moves)
+    }
+
+    /// What `color` gets back for the move it is playing now.
+    pub fn bonus(&self, color: PlayerColor) -> Bonus {
+        let (stage, _) = self.control.stage(self.moves[color_index(color)]);
+        self.control.stages[stage].bonus
+    }
+}
+
+/// Formats a clock reading as `h:mm:ss`, `m:ss`, or with tenths below ten seconds.
+pub fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else if seconds >= 10 {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    } else {
+        format!("{}.{}", seconds, time.subsec_millis() / 100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::clock::{Clock, TimeControl};
+    use crate::state::game::PlayerColor;
+    use std::time::{Duration, Instant};
+
+    const WHITE: PlayerColor = PlayerColor::White;
+    const BLACK: PlayerColor = PlayerColor::Black;
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    /// A clock for `spec` with white to move from `start`.
+    fn clock(spec: &str, start: Instant) -> Clock {
+        let mut clock = Clock::new(TimeControl::parse(spec).unwrap());
+        clock.start(WHITE, start);
+        clock
+    }
+
+    #[test]
+    fn increment() {
+        let start = Instant::now();
+        let mut clock = clock("60+5", start);
+        assert_eq!(clock.remaining(WHITE, start + secs(10)), secs(50));
+        clock.press(WHITE, start + secs(10));
+        assert_eq!(clock.remaining(WHITE, start + secs(20)), secs(55));
+        // only the side to move loses time
+        assert_eq!(clock.remaining(BLACK, start + secs(20)), secs(50));
+    }
+
+    #[test]
+    fn simple_delay() {
+        let start = Instant::now();
+        let mut clock = clock("60d5", start);
+        assert_eq!(clock.remaining(WHITE, start + secs(3)), secs(60));
+        clock.press(WHITE, start + secs(3));
+        assert_eq!(clock.remaining(WHITE, start + secs(3)), secs(60));
+        assert_eq!(clock.remaining(BLACK, start + secs(11)), secs(57));
+    }
+
+    #[test]
+    fn bronstein_delay() {
+        let start = Instant::now();
+        let mut clock = clock("60b5", start);
+        // the time used comes back, but no more than the delay
+        assert_eq!(clock.remaining(WHITE, start + secs(3)), secs(57));
+        clock.press(WHITE, start + secs(3));
+        assert_eq!(clock.remaining(WHITE, start + secs(3)), secs(60));
+        clock.press(BLACK, start + secs(11));
+        assert_eq!(clock.remaining(BLACK, start + secs(11)), secs(57));
+    }
+
+    #[test]
+    fn stages() {
+        let start = Instant::now();
+        let mut clock = clock("2/60:30", start);
+        clock.press(WHITE, start + secs(10));
+        clock.press(BLACK, start + secs(10));
+        assert_eq!(clock.remaining(WHITE, start + secs(10)), secs(50));
+        // the second move completes the first stage
+        clock.press(WHITE, start + secs(20));
+        assert_eq!(clock.remaining(WHITE, start + secs(20)), secs(70));
+    }
+
+    #[test]
+    fn flag_fall() {
+        let start = Instant::now();
+        let mut clock = clock("60+5", start);
+        assert_eq!(clock.flagged(start + secs(59)), None);
+        assert_eq!(clock.flagged(start + secs(60)), Some(WHITE));
+        assert_eq!(clock.remaining(WHITE, start + secs(90)), Duration::ZERO);
+        // a flag that fell stays down, the increment does not bring it back
+        clock.press(WHITE, start + secs(90));
+        assert_eq!(clock.remaining(WHITE, start + secs(90)), Duration::ZERO);
+        assert_eq!(clock.flagged(start + secs(90)), Some(WHITE));
+    }
+}
@@ -0,0 +1,608 @@
+use crate::state::bitboard::{color_index, kind_index, king_attacks, square_bit, Board};
+use crate::state::board::PieceKind;
+use crate::state::game::{
+    back_rank, CastleType, CastlingAvailability, GamePhase, GameState, Piece, PlayerColor,
+    Position, POCKET_KINDS,
+};
+use crate::state::variant::{Standard, Variant};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// No pocket can hold more pieces of a kind than the 16 pawns.
+const POCKET_LIMIT: u8 = 16;
+
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FenError {
+    WrongFieldCount(usize),
+    WrongRankCount(usize),
+    BadRankLength { rank: usize, length: usize },
+    UnknownPiece(char),
+    InvalidPockets(String),
+    MissingKing(PlayerColor),
+    TooManyKings(PlayerColor),
+    PawnOnBackRank(Position),
+    InvalidSideToMove(String),
+    InvalidCastling(String),
+    InvalidEnPassant(String),
+    InvalidHalfmoveClock(String),
+    InvalidFullmoveNumber(String),
+    OpponentInCheck,
+}
+
+impl Display for FenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FenError::WrongFieldCount(count) => {
+                write!(f, "expected 4 or 6 space separated fields, found {}", count)
+            }
+            FenError::WrongRankCount(count) => write!(f, "expected 8 ranks, found {}", count),
+            FenError::BadRankLength { rank, length } => {
+                write!(f, "rank {} describes {} squares instead of 8", rank, length)
+            }
+            FenError::UnknownPiece(c) => write!(f, "unknown piece letter '{}'", c),
+            FenError::InvalidPockets(field) => write!(f, "invalid pockets '{}'", field),
+            FenError::MissingKing(color) => write!(f, "{:?} has no king", color),
+            FenError::TooManyKings(color) => write!(f, "{:?} has more than one king", color),
+            FenError::PawnOnBackRank(position) => write!(f, "pawn on back rank at {}", position),
+            FenError::InvalidSideToMove(field) => write!(f, "invalid side to move '{}'", field),
+            FenError::InvalidCastling(field) => write!(f, "invalid castling rights '{}'", field),
+            FenError::InvalidEnPassant(field) => {
+                write!(f, "invalid en passant target '{}'", field)
+            }
+            FenError::InvalidHalfmoveClock(field) => {
+                write!(f, "invalid halfmove clock '{}'", field)
+            }
+            FenError::InvalidFullmoveNumber(field) => {
+                write!(f, "invalid fullmove number '{}'", field)
+            }
+            FenError::OpponentInCheck => write!(f, "the side not to move is in check"),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+fn piece_from_char(c: char) -> Option<Piece> {
+    let color = if c.is_ascii_uppercase() {
+        PlayerColor::White
+    } else {
+        PlayerColor::Black
+    };
+    let kind = match c.to_ascii_lowercase() {
+        'p' => PieceKind::Pawn,
+        'r' => PieceKind::Rook,
+        'n' => PieceKind::Knight,
+        'b' => PieceKind::Bishop,
+        'q' => PieceKind::Queen,
+        'k' => PieceKind::King,
+        _ => return None,
+    };
+    Some(Piece { kind, color })
+}
+
+fn piece_to_char(piece: Piece) -> char {
+    let c = match piece.kind {
+        PieceKind::Pawn => 'p',
+        PieceKind::Rook => 'r',
+        PieceKind::Knight => 'n',
+        PieceKind::Bishop => 'b',
+        PieceKind::Queen => 'q',
+        PieceKind::King => 'k',
+    };
+    match piece.color {
+        PlayerColor::Black => c,
+        PlayerColor::White => c.to_ascii_uppercase(),
+    }
+}
+
+impl GameState {
+    /// Builds a game of standard chess from a position in Forsyth-Edwards Notation.
+    pub fn from_fen(fen: &str) -> Result<GameState, FenError> {
+        Self::from_fen_with_variant(fen, &Standard)
+    }
+
+    /// Sets up a game of `variant` from a position in Forsyth-Edwards Notation.
+    pub fn from_fen_with_variant(
+        fen: &str,
+        variant: &'static dyn Variant,
+    ) -> Result<GameState, FenError> {
+        let mut fields = fen.split_whitespace().collect::<Vec<_>>();
+        // the clocks are commonly left out, e.g. in EPD records
+        if fields.len() == 4 {
+            fields.extend(["0", "1"]);
+        }
+        if fields.len() != 6 {
+            return Err(FenError::WrongFieldCount(fields.len()));
+        }
+
+        // Crazyhouse appends the pockets to the board in brackets, e.g. `.../RNBQKBNR[Qp]`
+        let (placement, pocket_letters) = match fields[0].split_once('[') {
+            Some((_, pockets)) if !variant.has_drops() => {
+                // nothing could read them back, the variant has no pockets
+                return Err(FenError::InvalidPockets(
+                    pockets.trim_end_matches(']').to_string(),
+                ));
+            }
+            Some((placement, pockets)) => {
+                let pockets = pockets
+                    .strip_suffix(']')
+                    .ok_or_else(|| FenError::InvalidPockets(pockets.to_string()))?;
+                (placement, pockets)
+            }
+            None => (fields[0], ""),
+        };
+        let mut pockets: [[u8; 6]; 2] = [[0; 6]; 2];
+        for c in pocket_letters.chars() {
+            let piece = piece_from_char(c)
+                .filter(|piece| piece.kind != PieceKind::King)
+                .ok_or_else(|| FenError::InvalidPockets(pocket_letters.to_string()))?;
+            let pocket = &mut pockets[color_index(piece.color)][kind_index(piece.kind)];
+            *pocket = pocket
+                .checked_add(1)
+                .filter(|count| *count <= POCKET_LIMIT)
+                .ok_or_else(|| FenError::InvalidPockets(pocket_letters.to_string()))?;
+        }
+
+        let ranks = placement.split('/').collect::<Vec<_>>();
+        if ranks.len() != 8 {
+            return Err(FenError::WrongRankCount(ranks.len()));
+        }
+
+        let mut board = [None; 64];
+        let mut promoted = 0;
+        let mut white_king = None;
+        let mut black_king = None;
+
+        for (y, rank) in ranks.iter().enumerate() {
+            let mut x = 0;
+            for c in rank.chars() {
+                if let Some(empty) = c.to_digit(10) {
+                    x += empty as usize;
+                    continue;
+                }
+                // a promoted piece in Crazyhouse, marked after its letter
+                if c == '~' {
+                    match x.checked_sub(1).map(|x| Position(x as i32, y as i32)) {
+                        Some(position) if x <= 8 && board[position.idx()].is_some() => {
+                            promoted |= square_bit(position.idx());
+                            continue;
+                        }
+                        _ => return Err(FenError::UnknownPiece(c)),
+                    }
+                }
+
+                let piece = piece_from_char(c).ok_or(FenError::UnknownPiece(c))?;
+                if x < 8 {
+                    let position = Position(x as i32, y as i32);
+                    if piece.kind == PieceKind::Pawn && (y == 0 || y == 7) {
+                        return Err(FenError::PawnOnBackRank(position));
+                    }
+                    if piece.kind == PieceKind::King {
+                        let king = match piece.color {
+                            PlayerColor::Black => &mut black_king,
+                            PlayerColor::White => &mut white_king,
+                        };
+                        if king.is_some() {
+                            return Err(FenError::TooManyKings(piece.color));
+                        }
+                        *king = Some(position);
+                    }
+                    board[position.idx()] = Some(piece);
+                }
+                x += 1;
+            }
+
+            if x != 8 {
+                return Err(FenError::BadRankLength {
+                    rank: 8 - y,
+                    length: x,
+                });
+            }
+        }
+
+        let white_king = white_king.ok_or(FenError::MissingKing(PlayerColor::White))?;
+        let black_king = black_king.ok_or(FenError::MissingKing(PlayerColor::Black))?;
+
+        let active_color = match fields[1] {
+            "w" => PlayerColor::White,
+            "b" => PlayerColor::Black,
+            other => return Err(FenError::InvalidSideToMove(other.to_string())),
+        };
+
+        let mut castling_white = CastlingAvailability {
+            long_side_available: false,
+            short_side_available: false,
+            ..CastlingAvailability::standard()
+        };
+        let mut castling_black = castling_white;
+        let mut chess960 = false;
+        if fields[2] != "-" {
+            let invalid = || FenError::InvalidCastling(fields[2].to_string());
+            for c in fields[2].chars() {
+                let (color, king, castling) = if c.is_ascii_uppercase() {
+                    (PlayerColor::White, white_king, &mut castling_white)
+                } else {
+                    (PlayerColor::Black, black_king, &mut castling_black)
+                };
+                let rank = back_rank(color);
+                if king.1 != rank {
+                    return Err(invalid());
+                }
+                let rook = Some(Piece {
+                    kind: PieceKind::Rook,
+                    color,
+                });
+                let mut rooks = (0..8).filter(|x| board[Position(*x, rank).idx()] == rook);
+
+                // X-FEN names the outermost rook by side, Shredder-FEN any rook by its file
+                let file = match c.to_ascii_lowercase() {
+                    'k' => rooks.rfind(|x| *x > king.0),
+                    'q' => rooks.find(|x| *x < king.0),
+                    file @ 'a'..='h' => {
+                        chess960 = true;
+                        rooks.find(|x| *x == file as i32 - 'a' as i32)
+                    }
+                    _ => None,
+                }
+                .ok_or_else(invalid)?;
+
+                let (available, rook_file) = if file > king.0 {
+                    (
+                        &mut castling.short_side_available,
+                        &mut castling.short_rook_file,
+                    )
+                } else {
+                    (
+                        &mut castling.long_side_available,
+                        &mut castling.long_rook_file,
+                    )
+                };
+                if *available {
+                    return Err(invalid());
+                }
+                *available = true;
+                *rook_file = file;
+                chess960 |= king.0 != 4 || (file != 0 && file != 7);
+            }
+        }
+
+        let previous_en_passe_move = if fields[3] == "-" {
+            None
+        } else {
+            let target = Position::from_algebraic(fields[3])
+                .ok_or_else(|| FenError::InvalidEnPassant(fields[3].to_string()))?;
+            // the target is the square the pawn skipped, the pawn itself stands one further
+            let (target_rank, pawn) = match active_color {
+                PlayerColor::White => (2, Position(target.0, target.1 + 1)),
+                PlayerColor::Black => (5, Position(target.0, target.1 - 1)),
+            };
+            let expected = Some(Piece {
+                kind: PieceKind::Pawn,
+                color: active_color.opponent(),
+            });
+            if target.1 != target_rank
+                || board[pawn.idx()] != expected
+                || board[target.idx()].is_some()
+            {
+                return Err(FenError::InvalidEnPassant(fields[3].to_string()));
+            }
+            Some(pawn)
+        };
+
+        let halfmove_clock = fields[4]
+            .parse::<u16>()
+            .map_err(|_| FenError::InvalidHalfmoveClock(fields[4].to_string()))?;
+        let fullmove_number = fields[5]
+            .parse::<u16>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| FenError::InvalidFullmoveNumber(fields[5].to_string()))?;
+
+        let mut state = GameState {
+            castling_white,
+            castling_black,
+            board: Board::from_mailbox(board),
+            state: GamePhase::Turn(active_color),
+            interaction: None,
+            draw_offer: None,
+            previous_en_passe_move,
+            active_color,
+            halfmove_clock,
+            fullmove_number,
+            hash: 0,
+            position_counter: HashMap::new(),
+            initial_fen: String::new(),
+            history: vec![],
+            undo_stack: vec![],
+            redo_stack: vec![],
+            clock: None,
+            chess960,
+            variant,
+            checks: [0; 2],
+            pockets,
+            promoted,
+            explosion: None,
+        };
+
+        let opponent_king = match active_color {
+            PlayerColor::Black => white_king,
+            PlayerColor::White => black_king,
+        };
+        // in atomic chess kings standing next to each other cannot be attacked
+        let kings_touch = king_attacks(white_king.idx()) & square_bit(black_king.idx()) != 0;
+        if state.position_is_attacked_by(opponent_king, active_color)
+            && !(variant.explodes() && kings_touch)
+        {
+            return Err(FenError::OpponentInCheck);
+        }
+
+        state.initial_fen = state.to_fen();
+        state.hash = state.compute_hash();
+        state.position_counter.insert(state.hash, 1);
+        state.detect_game_end();
+        state.detect_automatic_draw(1);
+        Ok(state)
+    }
+
+    /// Describes the current position in Forsyth-Edwards Notation.
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        let drops = self.variant.has_drops();
+
+        for y in 0..8 {
+            let mut empty = 0;
+            for x in 0..8 {
+                match self.piece_at(Position(x, y)) {
+                    None => empty += 1,
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push(piece_to_char(*piece));
+                        if drops && self.promoted & square_bit(Position(x, y).idx()) != 0 {
+                            fen.push('~');
+                        }
+                    }
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if y < 7 {
+                fen.push('/');
+            }
+        }
+        if drops {
+            fen.push('[');
+            for color in [PlayerColor::White, PlayerColor::Black] {
+                for kind in POCKET_KINDS {
+                    let piece = piece_to_char(Piece { kind, color });
+                    fen.extend(std::iter::repeat_n(
+                        piece,
+                        self.pocket(color, kind) as usize,
+                    ));
+                }
+            }
+            fen.push(']');
+        }
+
+        fen.push(' ');
+        fen.push(match self.active_color {
+            PlayerColor::Black => 'b',
+            PlayerColor::White => 'w',
+        });
+
+        fen.push(' ');
+        let castling_start = fen.len();
+        for (color, castling) in [
+            (PlayerColor::White, self.castling_white),
+            (PlayerColor::Black, self.castling_black),
+        ] {
+            for (side, available, file) in [
+                (
+                    CastleType::Short,
+                    castling.short_side_available,
+                    castling.short_rook_file,
+                ),
+                (
+                    CastleType::Long,
+                    castling.long_side_available,
+                    castling.long_rook_file,
+                ),
+            ] {
+                if available {
+                    fen.push(self.castling_letter(color, side, file));
+                }
+            }
+        }
+        if fen.len() == castling_start {
+            fen.push('-');
+        }
+
+        fen.push(' ');
+        match self.previous_en_passe_move {
+            Some(pawn) => {
+                let target = match self.active_color {
+                    PlayerColor::White => Position(pawn.0, pawn.1 - 1),
+                    PlayerColor::Black => Position(pawn.0, pawn.1 + 1),
+                };
+                fen.push_str(&target.to_string());
+            }
+            None => fen.push('-'),
+        }
+
+        fen.push_str(&format!(
+            " {} {}",
+            self.halfmove_clock, self.fullmove_number
+        ));
+
+        fen
+    }
+
+    /// The X-FEN letter of a castling right: `K` or `Q` for the outermost rook on that side,
+    /// else the file of the rook as in Shredder-FEN. Lowercase for black.
+    fn castling_letter(&self, color: PlayerColor, side: CastleType, rook_file: i32) -> char {
+        let rank = back_rank(color);
+        let king = self.king_position(color);
+        let rook = Piece {
+            kind: PieceKind::Rook,
+            color,
+        };
+        let mut rooks = (0..8).filter(|x| self.piece_at(Position(*x, rank)) == Some(&rook));
+        let outermost = match side {
+            CastleType::Short => rooks.rfind(|x| *x > king.0),
+            CastleType::Long => rooks.find(|x| *x < king.0),
+        };
+
+        let letter = match side {
+            _ if outermost != Some(rook_file) => (b'A' + rook_file as u8) as char,
+            CastleType::Short => 'K',
+            CastleType::Long => 'Q',
+        };
+        match color {
+            PlayerColor::Black => letter.to_ascii_lowercase(),
+            PlayerColor::White => letter,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::fen::{FenError, STARTING_FEN};
+    use crate::state::game::{GameState, PlayerColor, Position};
+    use crate::state::variant::Crazyhouse;
+
+    #[test]
+    fn errors() {
+        let invalid = |field: &str| field.to_string();
+        let cases = [
+            ("8/8/8/8/8/8/8/8 w -", FenError::WrongFieldCount(3)),
+            ("4k3/8/8/8/8/8/4K3 w - - 0 1", FenError::WrongRankCount(7)),
+            (
+                "4k3/8/8/8/8/8/8/4K4 w - - 0 1",
+                FenError::BadRankLength { rank: 1, length: 9 },
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K2 w - - 0 1",
+                FenError::BadRankLength { rank: 1, length: 7 },
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K2X w - - 0 1",
+                FenError::UnknownPiece('X'),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/8 w - - 0 1",
+                FenError::MissingKing(PlayerColor::White),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/3KK3 w - - 0 1",
+                FenError::TooManyKings(PlayerColor::White),
+            ),
+            (
+                "4k2P/8/8/8/8/8/8/4K3 w - - 0 1",
+                FenError::PawnOnBackRank(Position(7, 0)),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 x - - 0 1",
+                FenError::InvalidSideToMove(invalid("x")),
+            ),
+            // no rook to castle with, a right given twice, an unknown letter
+            (
+                "4k3/8/8/8/8/8/8/4K3 w K - 0 1",
+                FenError::InvalidCastling(invalid("K")),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K2R w KH - 0 1",
+                FenError::InvalidCastling(invalid("KH")),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K2R w X - 0 1",
+                FenError::InvalidCastling(invalid("X")),
+            ),
+            // no pawn that just moved two squares
+            (
+                "4k3/8/8/8/8/8/8/4K3 b - e3 0 1",
+                FenError::InvalidEnPassant(invalid("e3")),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - z9 0 1",
+                FenError::InvalidEnPassant(invalid("z9")),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - -1 1",
+                FenError::InvalidHalfmoveClock(invalid("-1")),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - 0 0",
+                FenError::InvalidFullmoveNumber(invalid("0")),
+            ),
+            ("4k3/8/8/8/8/8/4R3/4K3 w - - 0 1", FenError::OpponentInCheck),
+        ];
+        for (fen, error) in cases {
+            assert_eq!(GameState::from_fen(fen).err(), Some(error), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for fen in [
+            STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 13 42",
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9",
+        ] {
+            assert_eq!(GameState::from_fen(fen).unwrap().to_fen(), fen);
+        }
+
+        // Shredder-FEN castling rights come back in X-FEN
+        let shredder = "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9";
+        let state = GameState::from_fen(shredder).unwrap();
+        assert_eq!(state.to_fen().replace("KQkq", "HFhf"), shredder);
+
+        // the clocks may be left out
+        let state = GameState::from_fen("4k3/8/8/8/8/8/8/4K3 w - -").unwrap();
+        assert_eq!(state.to_fen(), "4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+    }
+
+    #[test]
+    fn pockets() {
+        for (fen, pockets) in [
+            ("4k3/8/8/8/8/8/8/4K3[X] w - - 0 1", "X"),
+            ("4k3/8/8/8/8/8/8/4K3[K] w - - 0 1", "K"),
+            ("4k3/8/8/8/8/8/8/4K3[P w - - 0 1", "P"),
+        ] {
+            assert_eq!(
+                GameState::from_fen_with_variant(fen, &Crazyhouse).err(),
+                Some(FenError::InvalidPockets(pockets.to_string())),
+                "{}",
+                fen
+            );
+        }
+
+        // only variants with drops have pockets, others could not write them back
+        for fen in [
+            "4k3/8/8/8/8/8/8/4K3[Q] w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3[] w - - 0 1",
+        ] {
+            assert!(matches!(
+                GameState::from_fen(fen),
+                Err(FenError::InvalidPockets(_))
+            ));
+        }
+
+        let pawns = "P".repeat(300);
+        let fen = format!("4k3/8/8/8/8/8/8/4K3[{}] w - - 0 1", pawns);
+        assert!(matches!(
+            GameState::from_fen_with_variant(&fen, &Crazyhouse),
+            Err(FenError::InvalidPockets(_))
+        ));
+
+        let fen = format!("4k3/8/8/8/8/8/8/4K3[{}] w - - 0 1", &pawns[..16]);
+        let state = GameState::from_fen_with_variant(&fen, &Crazyhouse).unwrap();
+        assert_eq!(state.to_fen(), fen);
+    }
+}
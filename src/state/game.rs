@@ -1,11 +1,40 @@
+use crate::state::bitboard::{
+    color_index, kind_index, king_attacks, square_bit, Bitboard, Board, LIGHT_SQUARES,
+};
 use crate::state::board::PieceKind;
-use std::cmp::{max, min};
+use crate::state::clock::{Clock, TimeControl};
+use crate::state::variant::{Standard, Variant};
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::time::Instant;
+
+/// What a pocket can hold, in the order it is shown.
+pub const POCKET_KINDS: [PieceKind; 5] = [
+    PieceKind::Queen,
+    PieceKind::Rook,
+    PieceKind::Bishop,
+    PieceKind::Knight,
+    PieceKind::Pawn,
+];
 
 #[derive(Copy, Clone, Debug)]
 pub struct CastlingAvailability {
-    long_side_available: bool,
-    short_side_available: bool,
+    pub(super) long_side_available: bool,
+    pub(super) short_side_available: bool,
+    /// The files the rooks castle from, a and h in standard chess but anywhere in Chess960.
+    pub(super) long_rook_file: i32,
+    pub(super) short_rook_file: i32,
+}
+
+impl CastlingAvailability {
+    pub(super) fn standard() -> Self {
+        Self {
+            long_side_available: true,
+            short_side_available: true,
+            long_rook_file: 0,
+            short_rook_file: 7,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
@@ -32,41 +61,140 @@ pub struct Piece {
 #[derive(Clone, Debug)]
 pub enum Interaction {
     StartMovingPiece(Position),
+    /// A piece was picked from a pocket to be dropped.
+    StartDroppingPiece(Piece),
     PlacedPiece(Position),
     PickingPromotion(Position, Position, Vec<Piece>),
     PickedPromotion(Piece),
+    ClaimDraw,
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
 }
 
 #[derive(Clone, Debug)]
 pub struct GameState {
+    pub(super) castling_white: CastlingAvailability,
+    pub(super) castling_black: CastlingAvailability,
+    pub(super) board: Board,
+    pub(super) state: GamePhase,
+    pub(super) previous_en_passe_move: Option<Position>,
+    pub(super) interaction: Option<Interaction>,
+    pub(super) draw_offer: Option<PlayerColor>,
+    pub(super) active_color: PlayerColor,
+    pub(super) halfmove_clock: u16,
+    pub(super) fullmove_number: u16,
+    pub(super) hash: u64,
+    pub(super) position_counter: HashMap<u64, u8>,
+    pub(super) initial_fen: String,
+    pub(super) history: Vec<(Position, Move)>,
+    pub(super) undo_stack: Vec<Snapshot>,
+    pub(super) redo_stack: Vec<(Position, Move)>,
+    pub(super) clock: Option<Clock>,
+    /// Castling moves are written king onto rook, as they are ambiguous otherwise.
+    pub(super) chess960: bool,
+    pub(super) variant: &'static dyn Variant,
+    /// How often each color gave check, by color index, in variants that count them.
+    pub(super) checks: [u8; 2],
+    /// The captured pieces each color may drop, by color and kind index.
+    pub(super) pockets: [[u8; 6]; 2],
+    /// The squares of pieces that were pawns once, they go back to the pocket as pawns.
+    pub(super) promoted: Bitboard,
+    /// The squares the last capture blew up in atomic chess, and when.
+    pub(super) explosion: Option<(Bitboard, Instant)>,
+}
+
+/// Everything a move can change, so taking it back restores the game exactly.
+#[derive(Clone, Debug)]
+pub(super) struct Snapshot {
     castling_white: CastlingAvailability,
     castling_black: CastlingAvailability,
-    white_king: Position,
-    black_king: Position,
-    board: Vec<Option<Piece>>,
+    board: Board,
     state: GamePhase,
-    // previous_state: Option<Box<GameState>>,
     previous_en_passe_move: Option<Position>,
-    interaction: Option<Interaction>,
-    moves_since_interesting: u8,
-    position_counter: HashMap<Vec<Option<Piece>>, u8>,
+    active_color: PlayerColor,
+    halfmove_clock: u16,
+    fullmove_number: u16,
+    hash: u64,
+    checks: [u8; 2],
+    pockets: [[u8; 6]; 2],
+    promoted: Bitboard,
+    clock: Option<Clock>,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum DrawReason {
+    /// Claimed after 50 moves by each side without a capture or pawn move.
     Fifty,
+    /// Claimed when the same position occurred for the third time.
     Repeat,
     Stalemate,
+    InsufficientMaterial,
+    /// 75 moves by each side without a capture or pawn move end the game on their own.
+    SeventyFive,
+    /// The same position occurring for the fifth time ends the game on its own.
+    FivefoldRepeat,
+    Agreement,
+    /// A flag fell, but the opponent could not have checkmated anyway.
+    TimeoutVsInsufficientMaterial,
+}
+
+impl Display for DrawReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            DrawReason::Fifty => "fifty-move rule",
+            DrawReason::Repeat => "threefold repetition",
+            DrawReason::Stalemate => "stalemate",
+            DrawReason::InsufficientMaterial => "insufficient material",
+            DrawReason::SeventyFive => "seventy-five-move rule",
+            DrawReason::FivefoldRepeat => "fivefold repetition",
+            DrawReason::Agreement => "agreement",
+            DrawReason::TimeoutVsInsufficientMaterial => "timeout vs insufficient material",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WinReason {
+    Checkmate,
+    Resignation,
+    /// The loser ran out of time.
+    Timeout,
+    /// The loser left the game.
+    Abandonment,
+    /// The winner's king reached the center in King of the Hill.
+    KingOfTheHill,
+    /// The winner gave check for the third time in Three-Check.
+    ThreeChecks,
+    /// The winner blew up the loser's king in Atomic.
+    Explosion,
+}
+
+impl Display for WinReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            WinReason::Checkmate => "checkmate",
+            WinReason::Resignation => "resignation",
+            WinReason::Timeout => "timeout",
+            WinReason::Abandonment => "abandonment",
+            WinReason::KingOfTheHill => "king of the hill",
+            WinReason::ThreeChecks => "three checks",
+            WinReason::Explosion => "explosion",
+        };
+        write!(f, "{}", reason)
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
 pub enum GamePhase {
-    Won(PlayerColor),
+    Won(PlayerColor, WinReason),
     Draw(DrawReason),
     Turn(PlayerColor),
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum CastleType {
     Long,
     Short,
@@ -80,30 +208,47 @@ pub struct CastlingMovement {
     pub king_end: Position,
 }
 
+/// The rank the pieces of `color` start on.
+pub fn back_rank(color: PlayerColor) -> i32 {
+    match color {
+        PlayerColor::Black => 0,
+        PlayerColor::White => 7,
+    }
+}
+
 impl CastleType {
-    pub fn positions(&self, color: PlayerColor) -> CastlingMovement {
-        let rank = match color {
-            PlayerColor::Black => 0,
-            PlayerColor::White => 7,
-        };
+    /// Where the king ends up, the c or g file wherever it started.
+    pub fn king_end(&self, color: PlayerColor) -> Position {
         match self {
-            CastleType::Long => CastlingMovement {
-                rook_start: Position(0, rank),
-                king_start: Position(4, rank),
-                rook_end: Position(3, rank),
-                king_end: Position(2, rank),
-            },
-            CastleType::Short => CastlingMovement {
-                rook_start: Position(7, rank),
-                king_start: Position(4, rank),
-                rook_end: Position(5, rank),
-                king_end: Position(6, rank),
-            },
+            CastleType::Long => Position(2, back_rank(color)),
+            CastleType::Short => Position(6, back_rank(color)),
+        }
+    }
+
+    /// Where the rook ends up, the d or f file wherever it started.
+    pub fn rook_end(&self, color: PlayerColor) -> Position {
+        match self {
+            CastleType::Long => Position(3, back_rank(color)),
+            CastleType::Short => Position(5, back_rank(color)),
         }
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct IllegalMove {
+    pub from: Position,
+    pub m: Move,
+}
+
+impl Display for IllegalMove {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} from {} is not a legal move", self.m, self.from)
+    }
+}
+
+impl std::error::Error for IllegalMove {}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Move {
     Move(Position),
     // to
@@ -112,116 +257,45 @@ pub enum Move {
     Promote(Position, Piece),
     // to, transform to
     Castle(CastleType), // rook
+    /// A piece from the pocket put onto an empty square.
+    Drop(PieceKind, Position),
 }
 
-impl GameState {
-    pub fn new() -> Self {
-        let mut board = vec![None; 64];
-
-        board[0] = Some(Piece {
-            kind: PieceKind::Rook,
-            color: PlayerColor::Black,
-        });
-        board[1] = Some(Piece {
-            kind: PieceKind::Knight,
-            color: PlayerColor::Black,
-        });
-        board[2] = Some(Piece {
-            kind: PieceKind::Bishop,
-            color: PlayerColor::Black,
-        });
-        board[3] = Some(Piece {
-            kind: PieceKind::Queen,
-            color: PlayerColor::Black,
-        });
-        board[4] = Some(Piece {
-            kind: PieceKind::King,
-            color: PlayerColor::Black,
-        });
-        board[5] = Some(Piece {
-            kind: PieceKind::Bishop,
-            color: PlayerColor::Black,
-        });
-        board[6] = Some(Piece {
-            kind: PieceKind::Knight,
-            color: PlayerColor::Black,
-        });
-        board[7] = Some(Piece {
-            kind: PieceKind::Rook,
-            color: PlayerColor::Black,
-        });
-
-        for x in 8..16 {
-            board[x] = Some(Piece {
-                kind: PieceKind::Pawn,
-                color: PlayerColor::Black,
-            })
+impl Move {
+    /// The square the moving piece ends up on.
+    pub fn target(&self, player: PlayerColor) -> Position {
+        match self {
+            Move::Move(to) => *to,
+            Move::Take(to, _) => *to,
+            Move::Promote(to, _) => *to,
+            Move::Castle(side) => side.king_end(player),
+            Move::Drop(_, to) => *to,
         }
+    }
+}
 
-        for x in 48..56 {
-            board[x] = Some(Piece {
-                kind: PieceKind::Pawn,
-                color: PlayerColor::White,
-            })
-        }
+impl GameState {
+    pub fn new() -> Self {
+        Self::with_variant(&Standard)
+    }
 
-        board[56] = Some(Piece {
-            kind: PieceKind::Rook,
-            color: PlayerColor::White,
-        });
-        board[57] = Some(Piece {
-            kind: PieceKind::Knight,
-            color: PlayerColor::White,
-        });
-        board[58] = Some(Piece {
-            kind: PieceKind::Bishop,
-            color: PlayerColor::White,
-        });
-        board[59] = Some(Piece {
-            kind: PieceKind::Queen,
-            color: PlayerColor::White,
-        });
-        board[60] = Some(Piece {
-            kind: PieceKind::King,
-            color: PlayerColor::White,
-        });
-        board[61] = Some(Piece {
-            kind: PieceKind::Bishop,
-            color: PlayerColor::White,
-        });
-        board[62] = Some(Piece {
-            kind: PieceKind::Knight,
-            color: PlayerColor::White,
-        });
-        board[63] = Some(Piece {
-            kind: PieceKind::Rook,
-            color: PlayerColor::White,
-        });
+    /// A game of `variant` from its start position.
+    pub fn with_variant(variant: &'static dyn Variant) -> Self {
+        Self::from_fen_with_variant(variant.start_fen(), variant)
+            .expect("Start positions are valid!")
+    }
 
-        Self {
-            castling_white: CastlingAvailability {
-                long_side_available: true,
-                short_side_available: true,
-            },
-            castling_black: CastlingAvailability {
-                long_side_available: true,
-                short_side_available: true,
-            },
-            white_king: Position(4, 7),
-            black_king: Position(4, 0),
-            board,
-            state: GamePhase::Turn(PlayerColor::White),
-            // previous_state: None,
-            interaction: None,
-            previous_en_passe_move: None,
-            moves_since_interesting: 0,
-            position_counter: HashMap::new(),
-        }
+    /// The position this game started from, before any move was played.
+    pub(super) fn initial_state(&self) -> GameState {
+        let mut state = Self::from_fen_with_variant(&self.initial_fen, self.variant)
+            .expect("Initial position is always valid!");
+        state.chess960 = self.chess960;
+        state
     }
 
     pub fn interact(mut self, interaction: Interaction) -> Self {
         let player = match self.state {
-            GamePhase::Won(_) => return self,
+            GamePhase::Won(..) => return self,
             GamePhase::Draw(_) => return self,
             GamePhase::Turn(player) => player,
         };
@@ -235,19 +309,39 @@ impl GameState {
                 }
                 self
             }
+            Interaction::StartDroppingPiece(piece) => {
+                if piece.color == player && self.pocket(player, piece.kind) > 0 {
+                    self.interaction = Some(interaction);
+                }
+                self
+            }
             Interaction::PlacedPiece(onto) => match &self.interaction {
                 Some(Interaction::StartMovingPiece(from)) if from != onto => {
-                    if let Some(piece) = self.board[from.idx()] {
+                    if let Some(piece) = self.piece_at(*from).copied() {
                         let moves = piece.moves(*from, &self);
-                        let moves = moves
+                        let mut moves = moves
                             .iter()
                             .filter(|m| match m {
                                 Move::Move(p) => p == onto,
                                 Move::Take(p, _) => p == onto,
                                 Move::Promote(p, _) => p == onto,
-                                Move::Castle(side) => side.positions(player).king_end == *onto,
+                                Move::Drop(..) => false,
+                                // the king is dropped onto its destination or onto the rook
+                                Move::Castle(side) => {
+                                    let castling = self.castling_movement(player, *side);
+                                    castling.king_end == *onto || castling.rook_start == *onto
+                                }
                             })
                             .collect::<Vec<_>>();
+                        // in Chess960 the king may also just step to where castling takes it
+                        if moves.len() > 1 && moves.iter().any(|m| !matches!(m, Move::Castle(_))) {
+                            moves.retain(|m| match m {
+                                Move::Castle(side) => {
+                                    self.castling_movement(player, *side).rook_start == *onto
+                                }
+                                _ => true,
+                            });
+                        }
 
                         if moves.len() <= 1 {
                             if let Some(m) = moves.get(0) {
@@ -282,44 +376,66 @@ impl GameState {
                     self.interaction = None;
                     self
                 }
+                Some(Interaction::StartDroppingPiece(piece)) => {
+                    let drop = Move::Drop(piece.kind, *onto);
+                    self.interaction = None;
+                    self.apply(*onto, drop).unwrap_or(self)
+                }
                 _ => self,
             },
             Interaction::PickedPromotion(choice) => {
                 if let Some(Interaction::PickingPromotion(from, onto, ..)) = self.interaction {
                     self.interaction = None;
-                    self.next(from, Move::Promote(onto, *choice))
+                    self.apply(from, Move::Promote(onto, *choice))
+                        .unwrap_or(self)
                 } else {
                     self.interaction = None;
                     self
                 }
             }
+            Interaction::Resign => {
+                self.state = GamePhase::Won(player.opponent(), WinReason::Resignation);
+                self.interaction = None;
+                self
+            }
+            Interaction::OfferDraw => {
+                self.draw_offer = Some(player);
+                self
+            }
+            // only the opponent of whoever offered may answer the offer
+            Interaction::AcceptDraw => {
+                if self.draw_offer == Some(player.opponent()) {
+                    self.state = GamePhase::Draw(DrawReason::Agreement);
+                    self.interaction = None;
+                }
+                self
+            }
+            Interaction::DeclineDraw => {
+                if self.draw_offer == Some(player.opponent()) {
+                    self.draw_offer = None;
+                }
+                self
+            }
+            Interaction::ClaimDraw => {
+                if let Some(reason) = self.claimable_draw() {
+                    self.state = GamePhase::Draw(reason);
+                    self.interaction = None;
+                }
+                self
+            }
             _ => self,
         }
     }
 
     pub fn position_is_attacked_by(&self, position: Position, player: PlayerColor) -> bool {
-        for x in 0..8 {
-            for y in 0..8 {
-                let pos = Position(x, y);
-                if let Some(piece) = self.board[pos.idx()] {
-                    if piece.color == player
-                        && piece
-                            .attacks(pos, self)
-                            .iter()
-                            .any(|target| *target == position)
-                    {
-                        return true;
-                    }
-                }
-            }
-        }
-
-        false
+        self.board
+            .attackers(position.idx(), player, self.board.occupied())
+            != 0
     }
 
-    fn next(&self, piece: Position, m: Move) -> GameState {
+    pub(super) fn next(&self, piece: Position, m: Move) -> GameState {
         let player = match self.state {
-            GamePhase::Won(_) => {
+            GamePhase::Won(..) => {
                 return self.clone();
             }
             GamePhase::Draw(_) => {
@@ -328,164 +444,347 @@ impl GameState {
             GamePhase::Turn(player) => player,
         };
 
-        let mut new = Self {
-            castling_white: self.castling_white,
-            castling_black: self.castling_black,
-            white_king: self.white_king,
-            black_king: self.black_king,
-            board: self.board.clone(),
-            state: self.state,
-            // previous_state: Some(Box::new(self.clone())),
-            interaction: self.interaction.clone(),
-            previous_en_passe_move: self.previous_en_passe_move,
-            moves_since_interesting: self.moves_since_interesting,
-            position_counter: self.position_counter.clone(),
-        };
+        if self.piece_at(piece).is_none() && !matches!(m, Move::Drop(..)) {
+            return self.clone();
+        }
 
-        let castling = match player {
-            PlayerColor::Black => &mut new.castling_black,
-            PlayerColor::White => &mut new.castling_white,
+        let mut new = self.clone();
+        new.history.push((piece, m));
+        new.undo_stack.push(self.snapshot());
+        new.redo_stack.clear();
+        // moving instead of answering declines the opponent's offer
+        if new.draw_offer == Some(player.opponent()) {
+            new.draw_offer = None;
+        }
+        new.make_move(piece, m);
+        let now = Instant::now();
+        let to = m.target(player);
+        let captures = match m {
+            Move::Take(..) => true,
+            Move::Promote(..) => self.piece_at(to).is_some(),
+            _ => false,
         };
+        new.explosion = (captures && self.variant.explodes())
+            .then(|| (square_bit(to.idx()) | king_attacks(to.idx()), now));
+        if let Some(clock) = &mut new.clock {
+            clock.press(player, now);
+        }
 
-        let from = piece.idx();
-        let moved_piece = match self.board[from] {
-            None => {
-                return self.clone();
-            }
-            Some(piece) => piece,
-        };
+        new.state = GamePhase::Turn(player.opponent());
+        let mut counter: u8 = new.position_counter.get(&new.hash).map_or(0, |x| *x);
+        counter += 1;
+        new.position_counter.insert(new.hash, counter);
 
-        if moved_piece.kind == PieceKind::King {
-            castling.long_side_available = false;
-            castling.short_side_available = false;
+        new.detect_game_end();
+        new.detect_automatic_draw(counter);
+        // a move completed after the flag fell does not count
+        if new.clock.as_ref().and_then(|clock| clock.flagged(now)) == Some(player) {
+            new.lose_on_time(player);
         }
 
-        if moved_piece.kind == PieceKind::Rook {
-            if piece.0 == 0 {
+        new
+    }
+
+    pub(super) fn revoke_castling_through(&mut self, square: Position) {
+        for color in [PlayerColor::Black, PlayerColor::White] {
+            let castling = match color {
+                PlayerColor::Black => &mut self.castling_black,
+                PlayerColor::White => &mut self.castling_white,
+            };
+            if square == Position(castling.long_rook_file, back_rank(color)) {
                 castling.long_side_available = false;
-            } else if piece.0 == 7 {
+            }
+            if square == Position(castling.short_rook_file, back_rank(color)) {
                 castling.short_side_available = false;
             }
         }
+    }
 
-        new.previous_en_passe_move = None;
-        new.moves_since_interesting += 1;
-
-        match m {
-            Move::Move(to) => {
-                if let Some(Piece {
-                    kind: PieceKind::Pawn,
-                    ..
-                }) = self.board[from]
-                {
-                    if (to.1 - piece.1).abs() == 2 {
-                        new.previous_en_passe_move = Some(to);
-                    }
-                    new.moves_since_interesting = 0;
-                }
+    /// The square the rook castling to `side` starts from.
+    pub(super) fn castling_rook(&self, color: PlayerColor, side: CastleType) -> Position {
+        let castling = match color {
+            PlayerColor::Black => self.castling_black,
+            PlayerColor::White => self.castling_white,
+        };
+        let file = match side {
+            CastleType::Long => castling.long_rook_file,
+            CastleType::Short => castling.short_rook_file,
+        };
+        Position(file, back_rank(color))
+    }
 
-                if let Some(Piece {
-                    kind: PieceKind::King,
-                    ..
-                }) = self.board[from]
-                {
-                    match player {
-                        PlayerColor::Black => new.black_king = to,
-                        PlayerColor::White => new.white_king = to,
-                    }
-                }
+    /// Where king and rook of `color` start and end when castling to `side` now.
+    pub fn castling_movement(&self, color: PlayerColor, side: CastleType) -> CastlingMovement {
+        CastlingMovement {
+            rook_start: self.castling_rook(color, side),
+            king_start: self.king_position(color),
+            rook_end: side.rook_end(color),
+            king_end: side.king_end(color),
+        }
+    }
 
-                let to = to.idx();
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            castling_white: self.castling_white,
+            castling_black: self.castling_black,
+            board: self.board,
+            state: self.state,
+            previous_en_passe_move: self.previous_en_passe_move,
+            active_color: self.active_color,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            hash: self.hash,
+            checks: self.checks,
+            pockets: self.pockets,
+            promoted: self.promoted,
+            clock: self.clock.clone(),
+        }
+    }
 
-                new.board[to] = new.board[from];
-                new.board[from] = None;
+    /// Takes back the last move, it can be replayed with [GameState::redo]. The clocks go
+    /// back to how they stood before the move, running for the side to move again.
+    pub fn undo(mut self) -> Self {
+        let snapshot = match self.undo_stack.pop() {
+            Some(snapshot) => snapshot,
+            None => return self,
+        };
+        let played = self
+            .history
+            .pop()
+            .expect("Every snapshot belongs to a move!");
+
+        if let Some(counter) = self.position_counter.get_mut(&self.hash) {
+            *counter -= 1;
+            if *counter == 0 {
+                self.position_counter.remove(&self.hash);
             }
-            Move::Take(to, victim) => {
-                if let Some(Piece {
-                    kind: PieceKind::King,
-                    ..
-                }) = self.board[from]
-                {
-                    match player {
-                        PlayerColor::Black => new.black_king = to,
-                        PlayerColor::White => new.white_king = to,
-                    }
-                }
+        }
+
+        self.castling_white = snapshot.castling_white;
+        self.castling_black = snapshot.castling_black;
+        self.board = snapshot.board;
+        self.state = snapshot.state;
+        self.previous_en_passe_move = snapshot.previous_en_passe_move;
+        self.active_color = snapshot.active_color;
+        self.halfmove_clock = snapshot.halfmove_clock;
+        self.fullmove_number = snapshot.fullmove_number;
+        self.hash = snapshot.hash;
+        self.checks = snapshot.checks;
+        self.pockets = snapshot.pockets;
+        self.promoted = snapshot.promoted;
+        self.clock = snapshot.clock;
+        if let Some(clock) = &mut self.clock {
+            clock.start(self.active_color, Instant::now());
+        }
+        self.explosion = None;
+        self.interaction = None;
+        self.draw_offer = None;
+        self.redo_stack.push(played);
 
-                let to = to.idx();
-                let victim = victim.idx();
-                new.board[victim] = None;
-                new.board[to] = new.board[from];
-                new.board[from] = None;
+        self
+    }
 
-                new.moves_since_interesting = 0;
-            }
-            Move::Promote(to, butterfly) => {
-                let to = to.idx();
-                new.board[to] = Some(butterfly);
-                new.board[from] = None;
-                new.moves_since_interesting = 0;
-            }
-            Move::Castle(side) => {
-                let CastlingMovement {
-                    rook_start,
-                    rook_end,
-                    king_end,
-                    ..
-                } = side.positions(player);
-
-                match player {
-                    PlayerColor::Black => new.black_king = king_end,
-                    PlayerColor::White => new.white_king = king_end,
-                }
+    /// Replays the last move taken back by [GameState::undo].
+    pub fn redo(mut self) -> Self {
+        let (from, m) = match self.redo_stack.pop() {
+            Some(played) => played,
+            None => return self,
+        };
+
+        self.interaction = None;
+        let mut next = self.next(from, m);
+        next.redo_stack = self.redo_stack;
+        next
+    }
+
+    /// Ends the game if the rules of the variant say it is over.
+    pub(super) fn detect_game_end(&mut self) {
+        let has_legal_moves = !self.legal_moves().is_empty();
+        if let Some(phase) = self.variant.game_end(self, has_legal_moves) {
+            self.state = phase;
+        }
+    }
 
-                new.board[rook_end.idx()] = new.board[rook_start.idx()];
-                new.board[king_end.idx()] = new.board[from];
+    /// Plays the rest of the game under `control`, starting the clock of the side to move.
+    pub fn with_clock(mut self, control: TimeControl) -> Self {
+        let mut clock = Clock::new(control);
+        clock.start(self.active_color, Instant::now());
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn clock(&self) -> Option<&Clock> {
+        self.clock.as_ref()
+    }
 
-                new.board[rook_start.idx()] = None;
-                new.board[from] = None;
+    /// Ends the game if the side to move ran out of time, and stops the clock once the game is over.
+    pub fn update_clock(mut self, now: Instant) -> Self {
+        let clock = match &mut self.clock {
+            Some(clock) => clock,
+            None => return self,
+        };
+        match self.state {
+            GamePhase::Turn(player) => {
+                if clock.flagged(now) == Some(player) {
+                    clock.stop(now);
+                    self.lose_on_time(player);
+                    self.interaction = None;
+                }
             }
+            _ => clock.stop(now),
+        }
+        self
+    }
+
+    fn lose_on_time(&mut self, player: PlayerColor) {
+        let winner = player.opponent();
+        self.state = if self.variant.can_win(self, winner) {
+            GamePhase::Won(winner, WinReason::Timeout)
+        } else {
+            GamePhase::Draw(DrawReason::TimeoutVsInsufficientMaterial)
         };
+    }
 
-        new.state = GamePhase::Turn(player.opponent());
-        let mut counter: u8 = new.position_counter.get(&new.board).map_or(0, |x| *x);
-        counter += 1;
-        new.position_counter.insert(new.board.clone(), counter);
-
-        let mut legal_moves = false;
-        for x in 0..8 {
-            for y in 0..8 {
-                let pos = Position(x, y);
-                if let Some(piece) = new.board[pos.idx()] {
-                    if piece.color != player && piece.moves(pos, &new).len() > 0 {
-                        legal_moves = true;
-                    }
-                }
+    /// Ends the game on draws nobody has to claim, `repetitions` being how often
+    /// the current position occurred. A checkmate on the last move still counts.
+    pub(super) fn detect_automatic_draw(&mut self, repetitions: u8) {
+        if let GamePhase::Turn(_) = self.state {
+            if repetitions >= 5 {
+                self.state = GamePhase::Draw(DrawReason::FivefoldRepeat);
+            } else if self.halfmove_clock >= 150 {
+                self.state = GamePhase::Draw(DrawReason::SeventyFive);
             }
         }
+    }
 
-        if !legal_moves {
-            if match player {
-                PlayerColor::Black => new.position_is_attacked_by(new.white_king, player),
-                PlayerColor::White => new.position_is_attacked_by(new.black_king, player),
-            } {
-                new.state = GamePhase::Won(player);
-            } else {
-                new.state = GamePhase::Draw(DrawReason::Stalemate);
+    /// The draw the side to move may claim right now, if any.
+    pub fn claimable_draw(&self) -> Option<DrawReason> {
+        if let GamePhase::Turn(_) = self.state {
+            if self
+                .position_counter
+                .get(&self.hash)
+                .is_some_and(|n| *n >= 3)
+            {
+                return Some(DrawReason::Repeat);
+            }
+            if self.halfmove_clock >= 100 {
+                return Some(DrawReason::Fifty);
             }
         }
-        if counter == 3 {
-            new.state = GamePhase::Draw(DrawReason::Repeat);
+        None
+    }
+
+    /// Every legal move of the side to move, empty once the game is over.
+    pub fn legal_moves(&self) -> Vec<(Position, Move)> {
+        let mut moves = vec![];
+        if let GamePhase::Turn(_) = self.state {
+            self.generate_moves(self.active_color, !0, &mut moves);
         }
-        if new.moves_since_interesting >= 50 {
-            new.state = GamePhase::Draw(DrawReason::Fifty);
+        moves
+    }
+
+    /// The legal moves of the piece on `from`, if it belongs to the side to move.
+    pub fn legal_moves_from(&self, from: Position) -> Vec<Move> {
+        let mut moves = vec![];
+        if let GamePhase::Turn(_) = self.state {
+            self.generate_moves(self.active_color, square_bit(from.idx()), &mut moves);
         }
+        moves.into_iter().map(|(_, m)| m).collect()
+    }
 
-        new
+    /// Plays a move without going through the [Interaction] state machine.
+    pub fn apply(&self, from: Position, m: Move) -> Result<GameState, IllegalMove> {
+        if self.legal_moves_from(from).contains(&m) {
+            let mut next = self.next(from, m);
+            next.interaction = None;
+            Ok(next)
+        } else {
+            Err(IllegalMove { from, m })
+        }
+    }
+
+    pub fn active_color(&self) -> PlayerColor {
+        self.active_color
+    }
+
+    /// Whether the king of the side to move is attacked. In atomic chess a king that was
+    /// blown up, or stands next to the enemy king, is never in check.
+    pub fn is_in_check(&self) -> bool {
+        let king = self.board.pieces(PieceKind::King, self.active_color);
+        let enemy_king = self
+            .board
+            .pieces(PieceKind::King, self.active_color.opponent());
+        if king == 0
+            || self.variant.explodes()
+                && king_attacks(king.trailing_zeros() as usize) & enemy_king != 0
+        {
+            return false;
+        }
+        let king = self.king_position(self.active_color);
+        self.position_is_attacked_by(king, self.active_color.opponent())
+    }
+
+    /// Whether neither side can possibly checkmate, whatever is played: only kings and
+    /// at most one knight or bishop, or bishops that all stand on squares of one color.
+    pub fn is_dead_position(&self) -> bool {
+        let board = &self.board;
+        let heavy = board.kind(PieceKind::Pawn)
+            | board.kind(PieceKind::Rook)
+            | board.kind(PieceKind::Queen);
+        if heavy != 0 {
+            return false;
+        }
+
+        let knights = board.kind(PieceKind::Knight);
+        let bishops = board.kind(PieceKind::Bishop);
+        if (knights | bishops).count_ones() <= 1 {
+            return true;
+        }
+
+        knights == 0 && (bishops & LIGHT_SQUARES == 0 || bishops & !LIGHT_SQUARES == 0)
+    }
+
+    /// Whether `color` could checkmate by any series of legal moves, the opponent's pieces
+    /// helping by blocking their own king (FIDE 6.9). A pawn, rook or queen always can, as
+    /// can two minor pieces that are not bishops of a single square color. A lone knight
+    /// needs any other piece of the opponent, bishops of one color one that can stand on the
+    /// squares of the other color.
+    pub fn has_mating_material(&self, color: PlayerColor) -> bool {
+        let board = &self.board;
+        let own = board.color(color);
+        let heavy = board.kind(PieceKind::Pawn)
+            | board.kind(PieceKind::Rook)
+            | board.kind(PieceKind::Queen);
+        if heavy & own != 0 {
+            return true;
+        }
+
+        let knights = board.kind(PieceKind::Knight) & own;
+        let bishops = board.kind(PieceKind::Bishop) & own;
+        let light = bishops & LIGHT_SQUARES != 0;
+        let dark = bishops & !LIGHT_SQUARES != 0;
+        if (knights | bishops).count_ones() >= 2 && (knights != 0 || (light && dark)) {
+            return true;
+        }
+
+        let blockers = board.color(color.opponent()) & !board.kind(PieceKind::King);
+        if knights != 0 {
+            blockers != 0
+        } else if bishops != 0 {
+            let bishop_squares = if light { LIGHT_SQUARES } else { !LIGHT_SQUARES };
+            blockers & !(board.kind(PieceKind::Bishop) & bishop_squares) != 0
+        } else {
+            false
+        }
     }
 
     pub fn piece_at(&self, position: Position) -> Option<&Piece> {
-        self.board[position.idx()].as_ref()
+        self.board.get(position.idx())
+    }
+
+    pub fn king_position(&self, color: PlayerColor) -> Position {
+        let king = self.board.pieces(PieceKind::King, color);
+        Position::from_idx(king.trailing_zeros() as usize)
     }
 
     pub fn phase(&self) -> GamePhase {
@@ -496,326 +795,413 @@ impl GameState {
         self.interaction.as_ref()
     }
 
-    pub fn is_valid_position_on_board(pos: Position) -> bool {
-        pos.0 >= 0 && pos.0 < 8 && pos.1 >= 0 && pos.1 < 8
+    /// The player whose draw offer is waiting for an answer.
+    pub fn draw_offer(&self) -> Option<PlayerColor> {
+        self.draw_offer
     }
-}
 
-const ROOK_MOVEMENT: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
-const QUEEN_MOVEMENT: [(i32, i32); 8] = [
-    (-1, 0),
-    (1, 0),
-    (0, -1),
-    (0, 1),
-    (-1, -1),
-    (-1, 1),
-    (1, -1),
-    (1, 1),
-];
-const KING_MOVEMENT: [(i32, i32); 8] = [
-    (-1, -1),
-    (-1, 1),
-    (1, -1),
-    (1, 1),
-    (0, -1),
-    (0, 1),
-    (-1, 0),
-    (1, 0),
-];
-const KNIGHT_MOVEMENT: [(i32, i32); 8] = [
-    (-1, -2),
-    (-1, 2),
-    (1, -2),
-    (1, 2),
-    (-2, -1),
-    (-2, 1),
-    (2, -1),
-    (2, 1),
-];
-const BISHOP_MOVEMENT: [(i32, i32); 4] = [(-1, -1), (-1, 1), (1, -1), (1, 1)];
+    /// The position the game started from, the moves of [GameState::history] lead from there.
+    pub fn initial_fen(&self) -> &str {
+        &self.initial_fen
+    }
 
-impl Piece {
-    fn add_attacks_single_step(start: Position, step: (i32, i32), positions: &mut Vec<Position>) {
-        let pos = Position(start.0 + step.0, start.1 + step.1);
-        if GameState::is_valid_position_on_board(pos) {
-            positions.push(pos)
-        }
-    }
-
-    fn add_attacks_pattern(
-        start: Position,
-        step: (i32, i32),
-        board: &GameState,
-        positions: &mut Vec<Position>,
-    ) {
-        let mut pos = Position(start.0 + step.0, start.1 + step.1);
-        loop {
-            if !GameState::is_valid_position_on_board(pos) {
-                break;
-            }
+    pub fn variant(&self) -> &'static dyn Variant {
+        self.variant
+    }
 
-            positions.push(pos);
+    /// How many captured pieces of `kind` `color` holds to drop.
+    pub fn pocket(&self, color: PlayerColor, kind: PieceKind) -> u8 {
+        self.pockets[color_index(color)][kind_index(kind)]
+    }
 
-            if let Some(_) = board.piece_at(pos) {
-                break;
-            }
+    /// How often `color` gave check in this game.
+    pub fn checks_given(&self, color: PlayerColor) -> u8 {
+        self.checks[color_index(color)]
+    }
 
-            pos = Position(pos.0 + step.0, pos.1 + step.1);
-        }
+    /// The squares the last move blew up in atomic chess and when it was played, if it did.
+    pub fn explosion(&self) -> Option<(Bitboard, Instant)> {
+        self.explosion
     }
 
-    pub fn attacks(&self, position: Position, board: &GameState) -> Vec<Position> {
-        let mut attacks = vec![];
+    /// Whether this is a game of Fischer Random Chess.
+    pub fn is_chess960(&self) -> bool {
+        self.chess960
+    }
 
-        match self.kind {
-            PieceKind::Pawn => {
-                let dir = match self.color {
-                    PlayerColor::Black => 1,
-                    PlayerColor::White => -1,
-                };
-                for side in [-1, 1] {
-                    Self::add_attacks_single_step(position, (side, dir), &mut attacks)
-                }
-            }
-            PieceKind::Rook => {
-                for dir in ROOK_MOVEMENT {
-                    Self::add_attacks_pattern(position, dir, board, &mut attacks)
-                }
-            }
-            PieceKind::Knight => {
-                for dir in KNIGHT_MOVEMENT {
-                    Self::add_attacks_single_step(position, dir, &mut attacks)
-                }
-            }
-            PieceKind::Bishop => {
-                for dir in BISHOP_MOVEMENT {
-                    Self::add_attacks_pattern(position, dir, board, &mut attacks)
-                }
-            }
-            PieceKind::King => {
-                for dir in KING_MOVEMENT {
-                    Self::add_attacks_single_step(position, dir, &mut attacks)
-                }
-            }
-            PieceKind::Queen => {
-                for dir in QUEEN_MOVEMENT {
-                    Self::add_attacks_pattern(position, dir, board, &mut attacks)
-                }
-            }
-        }
+    /// Every move played so far, together with the square it was played from.
+    pub fn history(&self) -> &[(Position, Move)] {
+        &self.history
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
 
-        attacks
+    /// Half moves since the last capture or pawn move.
+    pub fn halfmove_clock(&self) -> u16 {
+        self.halfmove_clock
     }
 
+    /// How often the position with the Zobrist key `key` occurred in this game.
+    pub fn occurrences(&self, key: u64) -> u8 {
+        self.position_counter.get(&key).copied().unwrap_or(0)
+    }
+}
+
+impl Piece {
+    /// The legal moves of this piece standing on `position`.
     pub fn moves(&self, position: Position, board: &GameState) -> Vec<Move> {
-        let mut local_board = board.clone();
         let mut moves = vec![];
-        let opponent = self.color.opponent();
-        let king_position = match self.color {
-            PlayerColor::Black => board.black_king,
-            PlayerColor::White => board.white_king,
-        };
+        board.generate_moves(self.color, square_bit(position.idx()), &mut moves);
+        moves.into_iter().map(|(_, m)| m).collect()
+    }
+}
 
-        match self.kind {
-            PieceKind::Pawn => {
-                // remove from board, i.e. detect pins
-                local_board.board[position.idx()] = None;
-
-                let dir = match self.color {
-                    PlayerColor::Black => 1,
-                    PlayerColor::White => -1,
-                };
-                let start_rank = match self.color {
-                    PlayerColor::Black => 1,
-                    PlayerColor::White => 6,
-                };
-                let promotion_rank = match self.color {
-                    PlayerColor::Black => 7,
-                    PlayerColor::White => 0,
-                };
-
-                // pawn forward
-                let forward = Position(position.0, position.1 + dir);
-                if let None = board.piece_at(forward) {
-                    local_board.board[forward.idx()] = Some(*self);
-
-                    if !local_board.position_is_attacked_by(king_position, opponent) {
-                        if forward.1 == promotion_rank {
-                            for kind in [
-                                PieceKind::Queen,
-                                PieceKind::Rook,
-                                PieceKind::Knight,
-                                PieceKind::Bishop,
-                            ] {
-                                moves.push(Move::Promote(
-                                    forward,
-                                    Piece {
-                                        color: self.color,
-                                        kind,
-                                    },
-                                ));
-                            }
-                        } else {
-                            moves.push(Move::Move(forward));
-                        }
-                    }
-                    local_board.board[forward.idx()] = None;
-
-                    let forward = Position(forward.0, forward.1 + dir);
-                    if start_rank == position.1 {
-                        if let None = board.piece_at(forward) {
-                            local_board.board[forward.idx()] = Some(*self);
-                            if !local_board.position_is_attacked_by(king_position, opponent) {
-                                moves.push(Move::Move(forward));
-                            }
-                            local_board.board[forward.idx()] = None;
-                        }
-                    }
-                }
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub struct Position(pub i32, pub i32);
 
-                // pawn take
-                for pos in self.attacks(position, board) {
-                    let target_piece = local_board.board[pos.idx()];
-
-                    // placing, i.e. detect blocking lines / removing attacks
-                    local_board.board[pos.idx()] = Some(*self);
-                    match target_piece {
-                        Some(Piece { color, .. }) => {
-                            if color == opponent {
-                                if !local_board.position_is_attacked_by(king_position, opponent) {
-                                    if pos.1 == promotion_rank {
-                                        for kind in [
-                                            PieceKind::Queen,
-                                            PieceKind::Rook,
-                                            PieceKind::Knight,
-                                            PieceKind::Bishop,
-                                        ] {
-                                            moves.push(Move::Promote(
-                                                pos,
-                                                Piece {
-                                                    color: self.color,
-                                                    kind,
-                                                },
-                                            ));
-                                        }
-                                    } else {
-                                        moves.push(Move::Take(pos, pos))
-                                    }
-                                }
-                            }
-                        }
-                        None => {
-                            let en_passe_pos = Position(pos.0, pos.1 - dir);
-                            if Some(en_passe_pos) == board.previous_en_passe_move {
-                                let en_passe_pawn = local_board.board[en_passe_pos.idx()];
-                                local_board.board[en_passe_pos.idx()] = None;
-
-                                if !local_board.position_is_attacked_by(king_position, opponent) {
-                                    moves.push(Move::Take(pos, en_passe_pos))
-                                }
+impl Position {
+    pub fn idx(&self) -> usize {
+        (self.0 + self.1 * 8) as usize
+    }
 
-                                local_board.board[en_passe_pos.idx()] = en_passe_pawn;
-                            }
-                        }
-                    }
+    pub fn from_idx(idx: usize) -> Position {
+        Position((idx % 8) as i32, (idx / 8) as i32)
+    }
 
-                    local_board.board[pos.idx()] = target_piece;
-                }
-            }
-            PieceKind::Queen | PieceKind::Rook | PieceKind::Bishop | PieceKind::Knight => {
-                // remove from board, i.e. detect pins
-                local_board.board[position.idx()] = None;
+    /// Parses a square in algebraic notation, e.g. `e4`.
+    pub fn from_algebraic(square: &str) -> Option<Position> {
+        let mut chars = square.chars();
+        let file = chars.next()?;
+        let rank = chars.next()?;
+        if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+            return None;
+        }
 
-                for pos in self.attacks(position, board).into_iter() {
-                    let target_piece = local_board.board[pos.idx()];
+        Some(Position(
+            file as i32 - 'a' as i32,
+            7 - (rank as i32 - '1' as i32),
+        ))
+    }
 
-                    // placing, i.e. detect blocking lines / removing attacks
-                    local_board.board[pos.idx()] = Some(*self);
+    pub fn file_char(&self) -> char {
+        (b'a' + self.0 as u8) as char
+    }
 
-                    match target_piece {
-                        None => {
-                            if !local_board.position_is_attacked_by(king_position, opponent) {
-                                moves.push(Move::Move(pos))
-                            }
-                        }
-                        Some(Piece { color, .. }) if color == opponent => {
-                            if !local_board.position_is_attacked_by(king_position, opponent) {
-                                moves.push(Move::Take(pos, pos))
-                            }
-                        }
-                        _ => {}
-                    }
+    pub fn rank_char(&self) -> char {
+        (b'8' - self.1 as u8) as char
+    }
+}
 
-                    local_board.board[pos.idx()] = target_piece;
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.file_char(), self.rank_char())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::clock::TimeControl;
+    use crate::state::fen::STARTING_FEN;
+    use crate::state::game::{
+        DrawReason, GamePhase, GameState, IllegalMove, Interaction, Move, PlayerColor, Position,
+        WinReason,
+    };
+    use crate::state::variant::{Crazyhouse, KingOfTheHill, Standard, Variant};
+    use std::time::{Duration, Instant};
+
+    fn play(mut state: GameState, moves: &[&str]) -> GameState {
+        for san in moves {
+            let (from, m) = state.parse_san(san).unwrap();
+            state = state.apply(from, m).unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn draw_offer_is_answered_by_the_opponent() {
+        let offered = GameState::new().interact(Interaction::OfferDraw);
+        assert_eq!(offered.draw_offer(), Some(PlayerColor::White));
+
+        // accepting or declining your own offer does nothing
+        let accepted = offered.clone().interact(Interaction::AcceptDraw);
+        assert!(matches!(
+            accepted.phase(),
+            GamePhase::Turn(PlayerColor::White)
+        ));
+        assert_eq!(accepted.draw_offer(), Some(PlayerColor::White));
+        let declined = offered.clone().interact(Interaction::DeclineDraw);
+        assert_eq!(declined.draw_offer(), Some(PlayerColor::White));
+
+        let answered = play(offered, &["e4"]);
+        let declined = answered.clone().interact(Interaction::DeclineDraw);
+        assert_eq!(declined.draw_offer(), None);
+        let accepted = answered.interact(Interaction::AcceptDraw);
+        assert!(matches!(
+            accepted.phase(),
+            GamePhase::Draw(DrawReason::Agreement)
+        ));
+    }
+
+    #[test]
+    fn resignation_and_draw_offers() {
+        let resigned = GameState::new().interact(Interaction::Resign);
+        assert!(matches!(
+            resigned.phase(),
+            GamePhase::Won(PlayerColor::Black, WinReason::Resignation)
+        ));
+        assert!(resigned.legal_moves().is_empty());
+
+        // an offer stays open while its side moves, and can be accepted
+        let offered = GameState::new().interact(Interaction::OfferDraw);
+        let offered = play(offered, &["e4"]);
+        assert_eq!(offered.draw_offer(), Some(PlayerColor::White));
+        let accepted = offered.clone().interact(Interaction::AcceptDraw);
+        assert!(matches!(
+            accepted.phase(),
+            GamePhase::Draw(DrawReason::Agreement)
+        ));
+
+        // declining it or moving on without an answer turns it down
+        let declined = offered.clone().interact(Interaction::DeclineDraw);
+        assert_eq!(declined.draw_offer(), None);
+        assert_eq!(play(offered, &["e5"]).draw_offer(), None);
+
+        // there is nothing to accept without an offer
+        let accepted = GameState::new().interact(Interaction::AcceptDraw);
+        assert!(matches!(
+            accepted.phase(),
+            GamePhase::Turn(PlayerColor::White)
+        ));
+    }
+
+    /// The result of white running out of time in `fen`.
+    fn white_flags(fen: &str, variant: &'static dyn Variant) -> GamePhase {
+        let control = TimeControl::parse("60").unwrap();
+        let state = GameState::from_fen_with_variant(fen, variant)
+            .unwrap()
+            .with_clock(control);
+        let later = Instant::now() + Duration::from_secs(61);
+        state.update_clock(later).phase()
+    }
+
+    #[test]
+    fn timeout_against_insufficient_material() {
+        // whether black still wins when white runs out of time
+        let cases: [(&str, &'static dyn Variant, bool); 7] = [
+            // a bare king cannot mate, whatever the opponent has
+            ("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", &Standard, false),
+            // the pawn can block its own king in, so a knight can mate
+            ("4k3/8/8/8/8/8/4P3/n3K3 w - - 0 1", &Standard, true),
+            // a bishop needs a piece that blocks the squares of the other color
+            ("4k3/8/8/8/8/8/B7/b3K3 w - - 0 1", &Standard, true),
+            ("4k3/8/8/8/8/8/8/nn2K3 w - - 0 1", &Standard, true),
+            // a bare king can still climb the hill
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 1", &KingOfTheHill, true),
+            // and a piece in a pocket be dropped
+            ("4k3/8/8/8/8/8/8/4K3[n] w - - 0 1", &Crazyhouse, true),
+            ("4k3/8/8/8/8/8/8/4K3[] w - - 0 1", &Crazyhouse, false),
+        ];
+        for (fen, variant, wins) in cases {
+            match white_flags(fen, variant) {
+                GamePhase::Won(PlayerColor::Black, WinReason::Timeout) => assert!(wins, "{}", fen),
+                GamePhase::Draw(DrawReason::TimeoutVsInsufficientMaterial) => {
+                    assert!(!wins, "{}", fen)
                 }
+                phase => panic!("{:?} after white ran out of time in {}", phase, fen),
             }
-            PieceKind::King => {
-                for pos in self.attacks(position, board).into_iter() {
-                    if board.position_is_attacked_by(pos, opponent) {
-                        continue;
-                    }
+        }
+    }
 
-                    match board.piece_at(pos) {
-                        None => moves.push(Move::Move(pos)),
-                        Some(other) if other.color == opponent => moves.push(Move::Take(pos, pos)),
-                        _ => {}
-                    };
-                }
+    #[test]
+    fn legal_moves_and_apply() {
+        let state = GameState::new();
+        assert_eq!(state.legal_moves().len(), 20);
+        let e2 = Position::from_algebraic("e2").unwrap();
+        let e5 = Position::from_algebraic("e5").unwrap();
+        assert_eq!(state.legal_moves_from(e2).len(), 2);
+        // only the side to move has moves
+        let e7 = Position::from_algebraic("e7").unwrap();
+        assert!(state.legal_moves_from(e7).is_empty());
+        assert_eq!(
+            state.apply(e2, Move::Move(e5)).err(),
+            Some(IllegalMove {
+                from: e2,
+                m: Move::Move(e5)
+            })
+        );
 
-                let castling = match self.color {
-                    PlayerColor::Black => board.castling_black,
-                    PlayerColor::White => board.castling_white,
-                };
+        // a pinned piece stays, a king in check has to step away
+        let pinned = GameState::from_fen("4k3/4r3/8/8/8/8/4N3/4K3 w - - 0 1").unwrap();
+        assert!(pinned.legal_moves_from(e2).is_empty());
+        let checked = GameState::from_fen("r3k3/8/8/8/8/5n2/8/4K3 w - - 0 1").unwrap();
+        assert!(checked.is_in_check());
+        assert_eq!(checked.legal_moves().len(), 4);
 
-                let mut castles = vec![];
-                if castling.short_side_available {
-                    castles.push(CastleType::Short)
-                }
-                if castling.long_side_available {
-                    castles.push(CastleType::Long)
-                }
+        let mated = play(GameState::new(), &["f3", "e5", "g4", "Qh4#"]);
+        assert!(mated.legal_moves().is_empty());
+    }
 
-                for castle in castles {
-                    let CastlingMovement {
-                        rook_start,
-                        king_start,
-                        ..
-                    } = castle.positions(self.color);
-                    let mut legal = true;
-                    let start = min(rook_start.0, king_start.0);
-                    let end = max(rook_start.0, king_start.0);
-                    for x in start..=end {
-                        let test_position = Position(x, rook_start.1);
-
-                        if x != start && x != end {
-                            if let Some(_) = board.piece_at(test_position) {
-                                legal = false;
-                                break;
-                            }
-                        }
+    #[test]
+    fn undo_and_redo() {
+        let start = play(GameState::new(), &["e4", "d5"]);
+        let played = play(start.clone(), &["exd5", "Qxd5", "Nc3"]);
+
+        // the capture comes back, and so does the en passant square
+        let undone = played.clone().undo().undo().undo();
+        assert_eq!(undone.to_fen(), start.to_fen());
+        assert_eq!(undone.history(), start.history());
+        let redone = undone.clone().redo().redo().redo();
+        assert_eq!(redone.to_fen(), played.to_fen());
+        assert_eq!(redone.history(), played.history());
+
+        // with nothing to take back or replay, the game stays as it is
+        assert_eq!(redone.redo().to_fen(), played.to_fen());
+        assert_eq!(GameState::new().undo().to_fen(), STARTING_FEN);
+
+        // a new move forgets the moves taken back
+        let branched = play(undone, &["d4"]);
+        assert_eq!(branched.clone().redo().to_fen(), branched.to_fen());
+
+        // a mate taken back gives the turn back
+        let mated = play(GameState::new(), &["f3", "e5", "g4", "Qh4#"]);
+        assert!(matches!(
+            mated.phase(),
+            GamePhase::Won(PlayerColor::Black, WinReason::Checkmate)
+        ));
+        let undone = mated.undo();
+        assert!(matches!(
+            undone.phase(),
+            GamePhase::Turn(PlayerColor::Black)
+        ));
+    }
 
-                        if board.position_is_attacked_by(test_position, opponent) {
-                            legal = false;
-                            break;
-                        }
-                    }
+    #[test]
+    fn undo_and_redo_restore_the_clock() {
+        let control = TimeControl::parse("2/60:30").unwrap();
+        let state = play(GameState::new().with_clock(control), &["e4"]);
+        let undone = state.undo();
+        let clock = undone.clock().unwrap();
+        let later = Instant::now() + Duration::from_secs(10);
+        // white is to move again and its clock runs, black's does not
+        assert!(clock.remaining(PlayerColor::White, later) <= Duration::from_secs(50));
+        assert_eq!(
+            clock.remaining(PlayerColor::Black, later),
+            Duration::from_secs(60)
+        );
+        assert_eq!(clock.moves_to_go(PlayerColor::White), Some(2));
+
+        // the move taken back does not count towards the first stage
+        let redone = undone.redo();
+        let clock = redone.clock().unwrap();
+        assert_eq!(clock.moves_to_go(PlayerColor::White), Some(1));
+        assert!(clock.remaining(PlayerColor::Black, later) < Duration::from_secs(51));
+    }
 
-                    if legal {
-                        moves.push(Move::Castle(castle))
-                    }
-                }
-            }
+    // knights out and back, the start position comes around again every four plies
+    const SHUFFLE: [&str; 4] = ["Nf3", "Nf6", "Ng1", "Ng8"];
+
+    #[test]
+    fn repetition() {
+        let mut state = GameState::new();
+        for _ in 0..2 {
+            state = play(state, &SHUFFLE);
         }
+        assert_eq!(state.occurrences(state.hash()), 3);
+        assert_eq!(state.claimable_draw(), Some(DrawReason::Repeat));
+        assert!(matches!(state.phase(), GamePhase::Turn(PlayerColor::White)));
+        let claimed = state.clone().interact(Interaction::ClaimDraw);
+        assert!(matches!(
+            claimed.phase(),
+            GamePhase::Draw(DrawReason::Repeat)
+        ));
+
+        state = play(state, &SHUFFLE[..3]);
+        assert!(matches!(state.phase(), GamePhase::Turn(PlayerColor::Black)));
+        state = play(state, &SHUFFLE[3..]);
+        assert_eq!(state.occurrences(state.hash()), 4);
+        state = play(state, &SHUFFLE);
+        assert!(matches!(
+            state.phase(),
+            GamePhase::Draw(DrawReason::FivefoldRepeat)
+        ));
+    }
 
-        moves
+    #[test]
+    fn move_rules() {
+        let fen = "4k3/8/8/8/8/8/8/R3K3 w - - 98 60";
+        let state = play(GameState::from_fen(fen).unwrap(), &["Ra2"]);
+        assert_eq!(state.claimable_draw(), None);
+        let state = play(state, &["Kd7"]);
+        assert_eq!(state.claimable_draw(), Some(DrawReason::Fifty));
+        let claimed = state.interact(Interaction::ClaimDraw);
+        assert!(matches!(
+            claimed.phase(),
+            GamePhase::Draw(DrawReason::Fifty)
+        ));
+
+        let fen = "4k3/8/8/8/8/8/8/R3K3 w - - 149 80";
+        let state = play(GameState::from_fen(fen).unwrap(), &["Ra2"]);
+        assert!(matches!(
+            state.phase(),
+            GamePhase::Draw(DrawReason::SeventyFive)
+        ));
+
+        // a mate on the 75th move still wins
+        let fen = "6k1/5ppp/8/8/8/8/8/R3K3 w - - 149 80";
+        let state = play(GameState::from_fen(fen).unwrap(), &["Ra8#"]);
+        assert!(matches!(
+            state.phase(),
+            GamePhase::Won(PlayerColor::White, WinReason::Checkmate)
+        ));
     }
-}
 
-#[derive(Eq, PartialEq, Copy, Clone, Debug)]
-pub struct Position(pub i32, pub i32);
+    #[test]
+    fn undo_redo_keeps_repetitions() {
+        let start = GameState::new();
+        let key = start.hash();
+        let mut state = start.clone();
+        for _ in 0..2 {
+            state = play(state, &SHUFFLE);
+        }
 
-impl Position {
-    pub fn idx(&self) -> usize {
-        (self.0 + self.1 * 8) as usize
+        let undone = state.clone().undo();
+        assert_eq!(undone.occurrences(key), 2);
+        assert_eq!(undone.claimable_draw(), None);
+        let redone = undone.redo();
+        assert_eq!(redone.occurrences(key), 3);
+        assert_eq!(redone.claimable_draw(), Some(DrawReason::Repeat));
+
+        let mut undone = redone;
+        for _ in 0..8 {
+            undone = undone.undo();
+        }
+        assert_eq!(undone.to_fen(), start.to_fen());
+        assert_eq!(undone.occurrences(key), 1);
+        assert_eq!(undone.occurrences(play(start, &SHUFFLE[..1]).hash()), 0);
+    }
+
+    #[test]
+    fn dead_positions() {
+        for (fen, dead) in [
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 1", true),
+            ("4k3/8/8/8/8/8/8/4KN2 w - - 0 1", true),
+            ("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1", true),
+            ("2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/8/3NKN2 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1", false),
+        ] {
+            let state = GameState::from_fen(fen).unwrap();
+            assert_eq!(state.is_dead_position(), dead, "{}", fen);
+        }
+
+        // taking the last rook ends the game at once
+        let state = GameState::from_fen("4k3/8/8/8/8/8/3r4/4K3 w - - 0 1").unwrap();
+        let state = play(state, &["Kxd2"]);
+        assert!(matches!(
+            state.phase(),
+            GamePhase::Draw(DrawReason::InsufficientMaterial)
+        ));
     }
 }
@@ -1,2 +1,12 @@
+pub mod bitboard;
 pub mod board;
+pub mod chess960;
+pub mod clock;
+pub mod fen;
 pub mod game;
+pub mod movegen;
+pub mod perft;
+pub mod pgn;
+pub mod san;
+pub mod variant;
+pub mod zobrist;
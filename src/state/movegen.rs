@@ -0,0 +1,525 @@
+use crate::state::bitboard::{
+    color_index, kind_index, king_attacks, pawn_attacks, piece_attacks, square_bit, squares,
+    Bitboard, RANK_1, RANK_8,
+};
+use crate::state::board::PieceKind;
+use crate::state::game::{
+    CastleType, CastlingAvailability, CastlingMovement, GameState, Move, Piece, PlayerColor,
+    Position, POCKET_KINDS,
+};
+use crate::state::zobrist::piece_key;
+
+/// What [GameState::make_move] cannot recompute when the move is taken back.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Unmake {
+    captured: Option<(usize, Piece)>,
+    castling_white: CastlingAvailability,
+    castling_black: CastlingAvailability,
+    previous_en_passe_move: Option<Position>,
+    halfmove_clock: u16,
+    fullmove_number: u16,
+    hash: u64,
+    checks: [u8; 2],
+    pockets: [[u8; 6]; 2],
+    promoted: Bitboard,
+    /// The pieces a capture blew up in atomic chess, the capturer among them.
+    exploded: [Option<(usize, Piece)>; 9],
+}
+
+fn forward(color: PlayerColor) -> isize {
+    match color {
+        PlayerColor::Black => 8,
+        PlayerColor::White => -8,
+    }
+}
+
+/// The squares on the rank of `a` from its file to the one of `b`, both included.
+fn rank_span(a: Position, b: Position) -> Bitboard {
+    (a.0.min(b.0)..=a.0.max(b.0)).fold(0, |span, x| span | square_bit(Position(x, a.1).idx()))
+}
+
+impl GameState {
+    /// Appends the legal moves of `color` for the pieces standing on `from`.
+    pub(crate) fn generate_moves(
+        &self,
+        color: PlayerColor,
+        from: Bitboard,
+        moves: &mut Vec<(Position, Move)>,
+    ) {
+        self.variant.generate_moves(self, color, from, moves);
+    }
+
+    /// Appends the moves of `color` for the pieces standing on `from` by the rules of
+    /// standard chess, keeping those `legal` allows.
+    pub(crate) fn generate_piece_moves(
+        &self,
+        color: PlayerColor,
+        from: Bitboard,
+        moves: &mut Vec<(Position, Move)>,
+        legal: impl Fn(Position, Move) -> bool,
+    ) {
+        let mut push = |from: Position, m: Move| {
+            if legal(from, m) {
+                moves.push((from, m));
+            }
+        };
+        let own = self.board.color(color);
+        let enemy = self.board.color(color.opponent());
+        let occupied = own | enemy;
+
+        for square in squares(own & from) {
+            let piece = match self.board.get(square) {
+                Some(piece) => *piece,
+                None => continue,
+            };
+            let origin = Position::from_idx(square);
+
+            match piece.kind {
+                PieceKind::Pawn => {
+                    let step = forward(color);
+                    let (start_rank, promotion_rank) = match color {
+                        PlayerColor::Black => (1, RANK_1),
+                        PlayerColor::White => (6, RANK_8),
+                    };
+
+                    let mut targets = pawn_attacks(color, square) & enemy;
+                    let one = (square as isize + step) as usize;
+                    if occupied & square_bit(one) == 0 {
+                        targets |= square_bit(one);
+                        let two = (one as isize + step) as usize;
+                        if square / 8 == start_rank && occupied & square_bit(two) == 0 {
+                            targets |= square_bit(two);
+                        }
+                    }
+
+                    for to in squares(targets) {
+                        let capture = enemy & square_bit(to) != 0;
+                        let position = Position::from_idx(to);
+                        if promotion_rank & square_bit(to) != 0 {
+                            for &kind in self.variant.promotions() {
+                                let promoted = Piece { kind, color };
+                                push(origin, Move::Promote(position, promoted));
+                            }
+                        } else if capture {
+                            push(origin, Move::Take(position, position));
+                        } else {
+                            push(origin, Move::Move(position));
+                        }
+                    }
+
+                    if let Some(victim) = self.previous_en_passe_move {
+                        let victim_square = victim.idx();
+                        let target = (victim_square as isize + step) as usize;
+                        if enemy & square_bit(victim_square) != 0
+                            && pawn_attacks(color, square) & square_bit(target) != 0
+                            && occupied & square_bit(target) == 0
+                        {
+                            let m = Move::Take(Position::from_idx(target), victim);
+                            push(origin, m);
+                        }
+                    }
+                }
+                PieceKind::King => {
+                    for to in squares(king_attacks(square) & !own) {
+                        let position = Position::from_idx(to);
+                        if enemy & square_bit(to) != 0 {
+                            push(origin, Move::Take(position, position));
+                        } else {
+                            push(origin, Move::Move(position));
+                        }
+                    }
+
+                    for castle in [CastleType::Short, CastleType::Long] {
+                        if self.can_castle(color, castle) {
+                            push(origin, Move::Castle(castle));
+                        }
+                    }
+                }
+                _ => {
+                    for to in squares(piece_attacks(piece, square, occupied) & !own) {
+                        let position = Position::from_idx(to);
+                        if enemy & square_bit(to) != 0 {
+                            push(origin, Move::Take(position, position));
+                        } else {
+                            push(origin, Move::Move(position));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Appends the drops of `color` onto the empty squares of `onto`, keeping those `legal` allows.
+    /// Pawns are never dropped onto the first or last rank.
+    pub(crate) fn generate_drops(
+        &self,
+        color: PlayerColor,
+        onto: Bitboard,
+        moves: &mut Vec<(Position, Move)>,
+        legal: impl Fn(Position, Move) -> bool,
+    ) {
+        let empty = onto & !self.board.occupied();
+        for kind in POCKET_KINDS {
+            if self.pocket(color, kind) == 0 {
+                continue;
+            }
+            let targets = match kind {
+                PieceKind::Pawn => empty & !(RANK_1 | RANK_8),
+                _ => empty,
+            };
+            for to in squares(targets) {
+                let to = Position::from_idx(to);
+                if legal(to, Move::Drop(kind, to)) {
+                    moves.push((to, Move::Drop(kind, to)));
+                }
+            }
+        }
+    }
+
+    /// Whether the king of `color` is safe after it plays `m` from `from`. Castling is
+    /// always safe, [GameState::can_castle] only allows it when it is.
+    pub(crate) fn leaves_king_safe(&self, color: PlayerColor, from: Position, m: Move) -> bool {
+        let king = self.board.pieces(PieceKind::King, color);
+        let (to, captured) = match m {
+            _ if king == 0 => return false,
+            Move::Castle(_) => return true,
+            // a drop moves nothing, `from` is where it lands
+            Move::Move(to) | Move::Promote(to, _) | Move::Drop(_, to) => (to.idx(), None),
+            // the victim of an en passant capture is not standing on the target square
+            Move::Take(to, victim) => (to.idx(), Some(victim.idx()).filter(|v| *v != to.idx())),
+        };
+        let from = from.idx();
+        let mut occupied = (self.board.occupied() & !square_bit(from)) | square_bit(to);
+        let mut enemy = self.board.color(color.opponent()) & !square_bit(to);
+        if let Some(captured) = captured {
+            occupied &= !square_bit(captured);
+            enemy &= !square_bit(captured);
+        }
+        let king = king.trailing_zeros() as usize;
+        let king = if from == king { to } else { king };
+
+        self.board.attackers(king, color.opponent(), occupied) & enemy == 0
+    }
+
+    /// Whether the king of `color` is safe after it plays `m` from `from` in atomic chess.
+    /// A king never captures, nor may it be blown up, but blowing up the enemy king wins
+    /// at once. Next to the enemy king it cannot be attacked, taking it would blow up both.
+    pub(crate) fn leaves_king_safe_atomic(
+        &self,
+        color: PlayerColor,
+        from: Position,
+        m: Move,
+    ) -> bool {
+        let king = self.board.pieces(PieceKind::King, color);
+        let enemy_king = self.board.pieces(PieceKind::King, color.opponent());
+        let from = square_bit(from.idx());
+        let (to, captured) = match m {
+            _ if king == 0 => return false,
+            Move::Castle(_) => return true,
+            Move::Take(..) if king & from != 0 => return false,
+            Move::Move(to) | Move::Drop(_, to) => (to.idx(), None),
+            Move::Promote(to, _) => (to.idx(), self.board.get(to.idx()).map(|_| to.idx())),
+            Move::Take(to, victim) => (to.idx(), Some(victim.idx())),
+        };
+        let mut occupied = self.board.occupied() & !from;
+        match captured {
+            Some(captured) => {
+                let blast = square_bit(to)
+                    | square_bit(captured)
+                    | (king_attacks(to) & !self.board.kind(PieceKind::Pawn));
+                if king & blast != 0 {
+                    return false;
+                }
+                if enemy_king & blast != 0 {
+                    return true;
+                }
+                occupied &= !blast;
+            }
+            None => occupied |= square_bit(to),
+        }
+        let king = if king & from != 0 {
+            to
+        } else {
+            king.trailing_zeros() as usize
+        };
+
+        king_attacks(king) & enemy_king != 0
+            || self.board.attackers(king, color.opponent(), occupied) == 0
+    }
+
+    fn can_castle(&self, color: PlayerColor, castle: CastleType) -> bool {
+        let castling = match color {
+            PlayerColor::Black => self.castling_black,
+            PlayerColor::White => self.castling_white,
+        };
+        let available = match castle {
+            CastleType::Long => castling.long_side_available,
+            CastleType::Short => castling.short_side_available,
+        };
+        if !available {
+            return false;
+        }
+
+        let CastlingMovement {
+            rook_start,
+            king_start,
+            rook_end,
+            king_end,
+        } = self.castling_movement(color, castle);
+        let rook = Piece {
+            kind: PieceKind::Rook,
+            color,
+        };
+        if self.board.get(rook_start.idx()) != Some(&rook) {
+            return false;
+        }
+
+        // every square king and rook cross has to be empty, apart from the two of them
+        let occupied = self.board.occupied();
+        let others = occupied & !square_bit(king_start.idx()) & !square_bit(rook_start.idx());
+        let crossed = rank_span(king_start, king_end) | rank_span(rook_start, rook_end);
+        if others & crossed != 0 {
+            return false;
+        }
+
+        // the king may not start on or pass through an attacked square
+        let passed = rank_span(king_start, king_end) & !square_bit(king_end.idx());
+        if squares(passed)
+            .any(|square| self.board.attackers(square, color.opponent(), occupied) != 0)
+        {
+            return false;
+        }
+        // nor end on one, where in Chess960 the rook may have been shielding it
+        let after = others | square_bit(king_end.idx()) | square_bit(rook_end.idx());
+        self.board
+            .attackers(king_end.idx(), color.opponent(), after)
+            == 0
+    }
+
+    /// Plays a legal move on the board, leaving the game bookkeeping to the caller.
+    pub(crate) fn make_move(&mut self, from: Position, m: Move) -> Unmake {
+        let player = self.active_color;
+        let mut unmake = Unmake {
+            captured: None,
+            castling_white: self.castling_white,
+            castling_black: self.castling_black,
+            previous_en_passe_move: self.previous_en_passe_move,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            hash: self.hash,
+            checks: self.checks,
+            pockets: self.pockets,
+            promoted: self.promoted,
+            exploded: [None; 9],
+        };
+        self.hash ^= self.state_key();
+
+        let moved = match m {
+            Move::Drop(kind, _) => {
+                let pocket = &mut self.pockets[color_index(player)][kind_index(kind)];
+                *pocket = pocket.saturating_sub(1);
+                Piece {
+                    kind,
+                    color: player,
+                }
+            }
+            _ => match self.remove_piece(from.idx()) {
+                Some(piece) => piece,
+                None => {
+                    self.hash ^= self.state_key();
+                    return unmake;
+                }
+            },
+        };
+        let was_promoted = self.promoted & square_bit(from.idx()) != 0;
+        self.promoted &= !square_bit(from.idx());
+
+        if moved.kind == PieceKind::King {
+            let castling = match player {
+                PlayerColor::Black => &mut self.castling_black,
+                PlayerColor::White => &mut self.castling_white,
+            };
+            castling.long_side_available = false;
+            castling.short_side_available = false;
+        }
+
+        // a rook leaving or being captured on its corner ends castling on that side
+        self.revoke_castling_through(from);
+
+        self.previous_en_passe_move = None;
+        self.halfmove_clock = self.halfmove_clock.saturating_add(1);
+
+        match m {
+            Move::Move(to) => {
+                if moved.kind == PieceKind::Pawn {
+                    if (to.1 - from.1).abs() == 2 {
+                        self.previous_en_passe_move = Some(to);
+                    }
+                    self.halfmove_clock = 0;
+                }
+                self.put_piece(to.idx(), moved);
+                if was_promoted {
+                    self.promoted |= square_bit(to.idx());
+                }
+            }
+            Move::Take(to, victim) => {
+                unmake.captured = self.capture(player, victim);
+                self.put_piece(to.idx(), moved);
+                if was_promoted {
+                    self.promoted |= square_bit(to.idx());
+                }
+                self.halfmove_clock = 0;
+            }
+            Move::Promote(to, promoted) => {
+                unmake.captured = self.capture(player, to);
+                self.put_piece(to.idx(), promoted);
+                self.promoted |= square_bit(to.idx());
+                self.halfmove_clock = 0;
+            }
+            Move::Castle(side) => {
+                let rook_start = self.castling_rook(player, side);
+                if let Some(rook) = self.remove_piece(rook_start.idx()) {
+                    self.put_piece(side.rook_end(player).idx(), rook);
+                }
+                self.put_piece(side.king_end(player).idx(), moved);
+            }
+            Move::Drop(kind, to) => {
+                if kind == PieceKind::Pawn {
+                    self.halfmove_clock = 0;
+                }
+                self.put_piece(to.idx(), moved);
+            }
+        }
+
+        if unmake.captured.is_some() && self.variant.explodes() {
+            unmake.exploded = self.explode(m.target(player));
+        }
+
+        if player == PlayerColor::Black {
+            self.fullmove_number += 1;
+        }
+        self.active_color = player.opponent();
+        if self.variant.counts_checks() && self.is_in_check() {
+            let checks = &mut self.checks[color_index(player)];
+            *checks = checks.saturating_add(1);
+        }
+        self.hash ^= self.state_key();
+
+        unmake
+    }
+
+    /// Takes back a move played by [GameState::make_move].
+    pub(crate) fn unmake_move(&mut self, from: Position, m: Move, unmake: Unmake) {
+        let player = self.active_color.opponent();
+
+        // the capturer comes back onto its target first, to be moved back from there
+        for (square, piece) in unmake.exploded.into_iter().flatten() {
+            self.board.put(square, piece);
+        }
+
+        match m {
+            Move::Move(to) | Move::Take(to, _) => {
+                if let Some(piece) = self.board.remove(to.idx()) {
+                    self.board.put(from.idx(), piece);
+                }
+            }
+            Move::Drop(_, to) => {
+                self.board.remove(to.idx());
+            }
+            Move::Promote(to, _) => {
+                self.board.remove(to.idx());
+                self.board.put(
+                    from.idx(),
+                    Piece {
+                        kind: PieceKind::Pawn,
+                        color: player,
+                    },
+                );
+            }
+            Move::Castle(side) => {
+                // in Chess960 either piece may return to where the other one stands
+                let king = self.board.remove(side.king_end(player).idx());
+                let rook = self.board.remove(side.rook_end(player).idx());
+                if let Some(king) = king {
+                    self.board.put(from.idx(), king);
+                }
+                if let Some(rook) = rook {
+                    self.board.put(self.castling_rook(player, side).idx(), rook);
+                }
+            }
+        }
+
+        if let Some((square, piece)) = unmake.captured {
+            self.board.put(square, piece);
+        }
+
+        self.castling_white = unmake.castling_white;
+        self.castling_black = unmake.castling_black;
+        self.previous_en_passe_move = unmake.previous_en_passe_move;
+        self.halfmove_clock = unmake.halfmove_clock;
+        self.fullmove_number = unmake.fullmove_number;
+        self.hash = unmake.hash;
+        self.checks = unmake.checks;
+        self.pockets = unmake.pockets;
+        self.promoted = unmake.promoted;
+        self.active_color = player;
+    }
+
+    /// Takes the piece on `square` off the board for `player`, into their pocket if the
+    /// variant has drops, and returns it together with its square.
+    fn capture(&mut self, player: PlayerColor, square: Position) -> Option<(usize, Piece)> {
+        self.revoke_castling_through(square);
+        let square = square.idx();
+        let captured = self.remove_piece(square)?;
+        let was_promoted = self.promoted & square_bit(square) != 0;
+        self.promoted &= !square_bit(square);
+
+        if self.variant.has_drops() {
+            let kind = if was_promoted {
+                PieceKind::Pawn
+            } else {
+                captured.kind
+            };
+            let pocket = &mut self.pockets[color_index(player)][kind_index(kind)];
+            *pocket = pocket.saturating_add(1);
+        }
+        Some((square, captured))
+    }
+
+    /// Blows up the piece on `square` and every piece but a pawn next to it, as a capture
+    /// does in atomic chess, and returns them together with their squares.
+    fn explode(&mut self, square: Position) -> [Option<(usize, Piece)>; 9] {
+        let square = square.idx();
+        let blast = square_bit(square) | (king_attacks(square) & !self.board.kind(PieceKind::Pawn));
+        let mut exploded = [None; 9];
+        for (slot, square) in exploded
+            .iter_mut()
+            .zip(squares(blast & self.board.occupied()))
+        {
+            self.revoke_castling_through(Position::from_idx(square));
+            self.promoted &= !square_bit(square);
+            *slot = self.remove_piece(square).map(|piece| (square, piece));
+            if let Some((_, piece)) = slot.filter(|(_, piece)| piece.kind == PieceKind::King) {
+                let castling = match piece.color {
+                    PlayerColor::Black => &mut self.castling_black,
+                    PlayerColor::White => &mut self.castling_white,
+                };
+                castling.long_side_available = false;
+                castling.short_side_available = false;
+            }
+        }
+        exploded
+    }
+
+    fn put_piece(&mut self, square: usize, piece: Piece) {
+        self.remove_piece(square);
+        self.hash ^= piece_key(piece, square);
+        self.board.put(square, piece);
+    }
+
+    fn remove_piece(&mut self, square: usize) -> Option<Piece> {
+        let piece = self.board.remove(square)?;
+        self.hash ^= piece_key(piece, square);
+        Some(piece)
+    }
+}
@@ -0,0 +1,201 @@
+use crate::state::game::{GameState, Move, Position};
+
+impl GameState {
+    /// Counts the leaf nodes of the legal move tree `depth` plies deep.
+    pub fn perft(&self, depth: u32) -> u64 {
+        self.clone().perft_in_place(depth)
+    }
+
+    fn perft_in_place(&mut self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        let mut moves = vec![];
+        self.generate_moves(self.active_color, !0, &mut moves);
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+
+        let mut nodes = 0;
+        for (from, m) in moves {
+            let unmake = self.make_move(from, m);
+            nodes += self.perft_in_place(depth - 1);
+            self.unmake_move(from, m, unmake);
+        }
+        nodes
+    }
+
+    /// Splits the perft count by the first move, to narrow down move generation bugs.
+    pub fn divide(&self, depth: u32) -> Vec<((Position, Move), u64)> {
+        self.legal_moves()
+            .into_iter()
+            .map(|(from, m)| {
+                let nodes = self.next(from, m).perft(depth.saturating_sub(1));
+                ((from, m), nodes)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::game::GameState;
+    use crate::state::variant::{Atomic, Crazyhouse, ThreeCheck};
+
+    // reference counts from https://www.chessprogramming.org/Perft_Results
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    const POSITION_3: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+    const POSITION_4: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+    const POSITION_4_MIRRORED: &str =
+        "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1";
+    const POSITION_5: &str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+    const POSITION_6: &str =
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+    // https://www.chessprogramming.org/Chess960_Perft_Results
+    const CHESS960_1: &str = "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9";
+    const CHESS960_2: &str = "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9";
+    const CHESS960_3: &str = "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9";
+
+    fn assert_perft(fen: &str, expected: &[u64]) {
+        let state = GameState::from_fen(fen).unwrap();
+        for (depth, nodes) in expected.iter().enumerate() {
+            assert_eq!(
+                state.perft(depth as u32 + 1),
+                *nodes,
+                "perft({}) of {}",
+                depth + 1,
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn start_position() {
+        assert_eq!(GameState::new().perft(0), 1);
+        assert_perft(
+            crate::state::fen::STARTING_FEN,
+            &[20, 400, 8_902, 197_281, 4_865_609],
+        );
+    }
+
+    #[test]
+    fn kiwipete() {
+        assert_perft(KIWIPETE, &[48, 2_039, 97_862, 4_085_603]);
+    }
+
+    #[test]
+    fn position_3() {
+        assert_perft(POSITION_3, &[14, 191, 2_812, 43_238, 674_624]);
+    }
+
+    #[test]
+    fn position_4() {
+        assert_perft(POSITION_4, &[6, 264, 9_467, 422_333]);
+        assert_perft(POSITION_4_MIRRORED, &[6, 264, 9_467, 422_333]);
+    }
+
+    #[test]
+    fn position_5() {
+        assert_perft(POSITION_5, &[44, 1_486, 62_379, 2_103_487]);
+    }
+
+    #[test]
+    fn position_6() {
+        assert_perft(POSITION_6, &[46, 2_079, 89_890, 3_894_594]);
+    }
+
+    #[test]
+    fn chess960() {
+        assert_perft(CHESS960_1, &[21, 528, 12_189, 326_672]);
+        assert_perft(CHESS960_2, &[21, 807, 18_002]);
+        assert_perft(CHESS960_3, &[20, 479, 10_471]);
+        assert_eq!(
+            GameState::new_chess960(518).unwrap().to_fen(),
+            crate::state::fen::STARTING_FEN
+        );
+    }
+
+    #[test]
+    fn crazyhouse() {
+        // the same as standard chess until a captured piece can be dropped on ply 5
+        assert_eq!(GameState::with_variant(&Crazyhouse).perft(5), 4_888_832);
+
+        let fen = "2k5/8/8/8/8/8/8/4K3[QRBNPqrbnp] w - - 0 1";
+        let mut state = GameState::from_fen_with_variant(fen, &Crazyhouse).unwrap();
+        // 5 king moves, 4 pieces onto 62 squares and a pawn onto the 48 not on the back ranks
+        assert_eq!(state.perft(1), 301);
+        assert_incremental_hash(&mut state, 2);
+    }
+
+    #[test]
+    fn atomic() {
+        // captures first happen on ply 4, explosions change the count from there
+        assert_eq!(GameState::with_variant(&Atomic).perft(4), 197_326);
+
+        // every exploded piece has to come back when the capture is taken back
+        let mut state = GameState::from_fen_with_variant(KIWIPETE, &Atomic).unwrap();
+        let key = state.hash();
+        assert_incremental_hash(&mut state, 3);
+        assert_eq!(state.hash(), key);
+        assert_eq!(state.to_fen(), KIWIPETE);
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let state = GameState::from_fen(KIWIPETE).unwrap();
+        let divided = state.divide(2);
+        assert_eq!(divided.len(), 48);
+        assert_eq!(divided.iter().map(|(_, n)| n).sum::<u64>(), 2_039);
+    }
+
+    fn assert_incremental_hash(state: &mut GameState, depth: u32) {
+        assert_eq!(state.hash(), state.compute_hash(), "{}", state.to_fen());
+        if depth == 0 {
+            return;
+        }
+        let mut moves = vec![];
+        state.generate_moves(state.active_color, !0, &mut moves);
+        for (from, m) in moves {
+            let unmake = state.make_move(from, m);
+            assert_incremental_hash(state, depth - 1);
+            state.unmake_move(from, m, unmake);
+        }
+    }
+
+    #[test]
+    fn incremental_hash() {
+        for fen in [KIWIPETE, POSITION_3, POSITION_4, POSITION_5, CHESS960_1] {
+            let mut state = GameState::from_fen(fen).unwrap();
+            let key = state.hash();
+            assert_incremental_hash(&mut state, 3);
+            assert_eq!(state.hash(), key);
+        }
+    }
+
+    #[test]
+    fn repetition_includes_side_to_move() {
+        // the same pieces with the other side to move is a different position
+        let a = GameState::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let b = GameState::from_fen("4k3/8/8/8/8/8/8/4K3 b - - 0 1").unwrap();
+        assert_ne!(a.hash(), b.hash());
+
+        // an en passant capture nobody can play does not count
+        let c = GameState::from_fen("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1").unwrap();
+        let d = GameState::from_fen("4k3/8/8/8/4P3/8/8/4K3 b - - 0 1").unwrap();
+        assert_eq!(c.hash(), d.hash());
+
+        // in Three-check the same pieces after a check are one check closer to the end
+        let fen = "4k3/8/8/8/8/8/8/R3K3 w - - 0 1";
+        let start = GameState::from_fen_with_variant(fen, &ThreeCheck).unwrap();
+        let mut after = start.clone();
+        for san in ["Ra8+", "Ke7", "Ra1", "Ke8"] {
+            let (from, m) = after.parse_san(san).unwrap();
+            after = after.apply(from, m).unwrap();
+        }
+        assert!(after.to_fen().starts_with("4k3/8/8/8/8/8/8/R3K3 w - -"));
+        assert_ne!(after.hash(), start.hash());
+        assert_eq!(after.hash(), after.compute_hash());
+        assert_eq!(after.occurrences(after.hash()), 1);
+    }
+}
@@ -0,0 +1,691 @@
+use crate::state::fen::FenError;
+use crate::state::game::{
+    DrawReason, GamePhase, GameState, Move, PlayerColor, Position, WinReason,
+};
+use crate::state::san::SanError;
+use crate::state::variant::{variant_by_name, Standard, Variant};
+use std::fmt::{Display, Formatter};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAX_LINE_LENGTH: usize = 79;
+
+/// The Seven Tag Roster, minus the result which is taken from the game itself.
+#[derive(Clone, Debug)]
+pub struct PgnTags {
+    pub event: String,
+    pub site: String,
+    pub date: String,
+    pub round: String,
+    pub white: String,
+    pub black: String,
+}
+
+impl Default for PgnTags {
+    fn default() -> Self {
+        Self {
+            event: "?".to_string(),
+            site: "?".to_string(),
+            date: "????.??.??".to_string(),
+            round: "?".to_string(),
+            white: "?".to_string(),
+            black: "?".to_string(),
+        }
+    }
+}
+
+impl PgnTags {
+    pub fn dated(mut self, time: SystemTime) -> Self {
+        let days = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() / 86_400) as i64;
+
+        // civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        self.date = format!("{:04}.{:02}.{:02}", year, month, day);
+        self
+    }
+}
+
+pub fn result_token(phase: GamePhase) -> &'static str {
+    match phase {
+        GamePhase::Won(PlayerColor::White, _) => "1-0",
+        GamePhase::Won(PlayerColor::Black, _) => "0-1",
+        GamePhase::Draw(_) => "1/2-1/2",
+        GamePhase::Turn(_) => "*",
+    }
+}
+
+/// Describes how the game ended for the `Termination` tag.
+pub fn termination(phase: GamePhase) -> Option<String> {
+    match phase {
+        GamePhase::Won(winner, reason) => Some(format!("{:?} won by {}", winner, reason)),
+        GamePhase::Draw(reason) => Some(format!("Game drawn by {}", reason)),
+        GamePhase::Turn(_) => None,
+    }
+}
+
+/// Reads back a phase from a result token and the `Termination` tag written by [termination].
+/// Anything decisive that does not say otherwise is taken to be a resignation.
+fn phase_from_result(result: &str, termination: &str) -> Option<GamePhase> {
+    let termination = termination.to_lowercase();
+    let win_reason = if termination.contains("time") {
+        WinReason::Timeout
+    } else if termination.contains("abandon") {
+        WinReason::Abandonment
+    } else {
+        WinReason::Resignation
+    };
+    let draw_reason = if termination.contains("repetition") {
+        DrawReason::Repeat
+    } else if termination.contains("fifty") {
+        DrawReason::Fifty
+    } else if termination.contains("insufficient") {
+        DrawReason::InsufficientMaterial
+    } else {
+        DrawReason::Agreement
+    };
+
+    match result {
+        "1-0" => Some(GamePhase::Won(PlayerColor::White, win_reason)),
+        "0-1" => Some(GamePhase::Won(PlayerColor::Black, win_reason)),
+        "1/2-1/2" => Some(GamePhase::Draw(draw_reason)),
+        _ => None,
+    }
+}
+
+/// Whether a `Variant` tag names Fischer Random Chess, which goes by a few names.
+fn is_chess960_variant(variant: &str) -> bool {
+    let variant = variant.to_lowercase();
+    variant.contains("960") || variant.contains("fischer")
+}
+
+/// The variant the `Variant` tag names, standard chess without one or for unknown names.
+fn tagged_variant(tags: &[(String, String)]) -> &'static dyn Variant {
+    tags.iter()
+        .find(|(tag, _)| tag == "Variant")
+        .and_then(|(_, variant)| variant_by_name(variant))
+        .unwrap_or(&Standard)
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl GameState {
+    /// Exports the game played so far in Portable Game Notation.
+    pub fn to_pgn(&self, tags: &PgnTags) -> String {
+        let result = result_token(self.phase());
+        let mut pgn = String::new();
+
+        for (name, value) in [
+            ("Event", tags.event.as_str()),
+            ("Site", &tags.site),
+            ("Date", &tags.date),
+            ("Round", &tags.round),
+            ("White", &tags.white),
+            ("Black", &tags.black),
+            ("Result", result),
+        ] {
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, escape(value)));
+        }
+        if let Some(clock) = self.clock() {
+            pgn.push_str(&format!("[TimeControl \"{}\"]\n", clock.time_control()));
+        }
+        if let Some(termination) = termination(self.phase()) {
+            pgn.push_str(&format!("[Termination \"{}\"]\n", escape(&termination)));
+        }
+        if self.chess960 {
+            pgn.push_str("[Variant \"Chess960\"]\n");
+        } else if self.variant.name() != Standard.name() {
+            pgn.push_str(&format!("[Variant \"{}\"]\n", self.variant.name()));
+        }
+        if self.initial_fen != self.variant.start_fen() {
+            pgn.push_str("[SetUp \"1\"]\n");
+            pgn.push_str(&format!("[FEN \"{}\"]\n", self.initial_fen));
+        }
+        pgn.push('\n');
+
+        let mut replay = self.initial_state();
+        let mut tokens = vec![];
+        for (ply, (from, m)) in self.history().iter().enumerate() {
+            match replay.active_color {
+                PlayerColor::White => tokens.push(format!("{}.", replay.fullmove_number)),
+                PlayerColor::Black if ply == 0 => {
+                    tokens.push(format!("{}...", replay.fullmove_number))
+                }
+                PlayerColor::Black => {}
+            }
+            tokens.push(replay.to_san(*from, *m));
+            replay = replay.next(*from, *m);
+        }
+        tokens.push(result.to_string());
+
+        let mut line_length = 0;
+        for token in tokens {
+            if line_length > 0 && line_length + 1 + token.len() > MAX_LINE_LENGTH {
+                pgn.push('\n');
+                line_length = 0;
+            } else if line_length > 0 {
+                pgn.push(' ');
+                line_length += 1;
+            }
+            line_length += token.len();
+            pgn.push_str(&token);
+        }
+        pgn.push('\n');
+
+        pgn
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PgnErrorKind {
+    UnterminatedTag,
+    UnterminatedString,
+    UnterminatedComment,
+    UnbalancedVariation,
+    UnexpectedCharacter(char),
+    InvalidFen(FenError),
+    InvalidMove(SanError),
+    GameOver(String),
+}
+
+/// A problem found while reading PGN, located by 1-based line and column.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PgnError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: PgnErrorKind,
+}
+
+impl Display for PgnError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match &self.kind {
+            PgnErrorKind::UnterminatedTag => write!(f, "unterminated tag pair"),
+            PgnErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            PgnErrorKind::UnterminatedComment => write!(f, "unterminated comment"),
+            PgnErrorKind::UnbalancedVariation => write!(f, "unbalanced variation parenthesis"),
+            PgnErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character '{}'", c),
+            PgnErrorKind::InvalidFen(e) => write!(f, "invalid FEN tag: {}", e),
+            PgnErrorKind::InvalidMove(e) => write!(f, "{}", e),
+            PgnErrorKind::GameOver(san) => write!(f, "move '{}' played after the game ended", san),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+/// A single game read from PGN.
+#[derive(Clone, Debug)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<(Position, Move)>,
+    pub result: String,
+    start: GameState,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The game after the first `ply` half moves have been played.
+    pub fn state_at(&self, ply: usize) -> GameState {
+        self.moves
+            .iter()
+            .take(ply)
+            .fold(self.start.clone(), |state, (from, m)| state.next(*from, *m))
+    }
+
+    /// The game after all moves, ended the way the result says if the moves alone did not end it.
+    pub fn final_state(&self) -> GameState {
+        let mut state = self.state_at(self.moves.len());
+        if let GamePhase::Turn(_) = state.phase() {
+            let termination = self.tag("Termination").unwrap_or_default();
+            if let Some(phase) = phase_from_result(&self.result, termination) {
+                state.state = phase;
+            }
+        }
+        state
+    }
+}
+
+struct Cursor<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl Cursor<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, kind: PgnErrorKind) -> PgnError {
+        PgnError {
+            line: self.line,
+            column: self.column,
+            kind,
+        }
+    }
+
+    fn take_while(&mut self, accept: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(c) = self.peek() {
+            if !accept(c) {
+                break;
+            }
+            taken.push(c);
+            self.bump();
+        }
+        taken
+    }
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_+#=:-/!?@".contains(c)
+}
+
+/// Reads every game in a PGN database.
+pub fn parse_pgn(text: &str) -> Result<Vec<PgnGame>, PgnError> {
+    let mut cursor = Cursor {
+        chars: text.chars().peekable(),
+        line: 1,
+        column: 1,
+    };
+
+    let mut games = vec![];
+    let mut tags = vec![];
+    let mut moves = vec![];
+    let mut state: Option<GameState> = None;
+    // where the FEN tag of the current game is, to locate an invalid one
+    let mut fen_tag = (1, 1);
+    let mut variation_depth = 0;
+
+    while let Some(c) = cursor.peek() {
+        let (line, column) = (cursor.line, cursor.column);
+        let located = |kind| PgnError { line, column, kind };
+
+        match c {
+            c if c.is_whitespace() => {
+                cursor.bump();
+            }
+            '%' if column == 1 => {
+                cursor.take_while(|c| c != '\n');
+            }
+            ';' => {
+                cursor.take_while(|c| c != '\n');
+            }
+            '{' => {
+                cursor.take_while(|c| c != '}');
+                if cursor.bump().is_none() {
+                    return Err(located(PgnErrorKind::UnterminatedComment));
+                }
+            }
+            '(' => {
+                cursor.bump();
+                variation_depth += 1;
+            }
+            ')' => {
+                cursor.bump();
+                if variation_depth == 0 {
+                    return Err(located(PgnErrorKind::UnbalancedVariation));
+                }
+                variation_depth -= 1;
+            }
+            '$' => {
+                cursor.bump();
+                cursor.take_while(|c| c.is_ascii_digit());
+            }
+            '[' if variation_depth == 0 => {
+                cursor.bump();
+                cursor.take_while(char::is_whitespace);
+                let name = cursor.take_while(is_symbol_char);
+                cursor.take_while(char::is_whitespace);
+                if cursor.bump() != Some('"') {
+                    return Err(located(PgnErrorKind::UnterminatedTag));
+                }
+
+                let mut value = String::new();
+                loop {
+                    match cursor.bump() {
+                        Some('"') => break,
+                        Some('\\') => value.extend(cursor.bump()),
+                        Some(c) => value.push(c),
+                        None => return Err(located(PgnErrorKind::UnterminatedString)),
+                    }
+                }
+
+                cursor.take_while(char::is_whitespace);
+                if cursor.bump() != Some(']') {
+                    return Err(located(PgnErrorKind::UnterminatedTag));
+                }
+
+                if name == "FEN" {
+                    fen_tag = (line, column);
+                }
+                tags.push((name, value));
+            }
+            '*' if variation_depth == 0 => {
+                cursor.bump();
+                games.push(finish_game(
+                    &mut tags, &mut moves, &mut state, fen_tag, "*",
+                )?);
+            }
+            '*' => {
+                cursor.bump();
+            }
+            c if is_symbol_char(c) => {
+                let symbol = cursor.take_while(is_symbol_char);
+                if symbol.chars().all(|c| c.is_ascii_digit()) {
+                    // move number indication, e.g. "12." or "12..."
+                    cursor.take_while(|c| c == '.');
+                    continue;
+                }
+                if variation_depth > 0 {
+                    continue;
+                }
+
+                if ["1-0", "0-1", "1/2-1/2"].contains(&symbol.as_str()) {
+                    games.push(finish_game(
+                        &mut tags, &mut moves, &mut state, fen_tag, &symbol,
+                    )?);
+                    continue;
+                }
+
+                // the tags are complete once the movetext starts, whatever their order
+                let current = match &mut state {
+                    Some(current) => current,
+                    None => state.insert(tagged_start(&tags, fen_tag)?),
+                };
+                if !matches!(current.phase(), GamePhase::Turn(_)) {
+                    return Err(located(PgnErrorKind::GameOver(symbol)));
+                }
+                let (from, m) = current
+                    .parse_san(&symbol)
+                    .map_err(|e| located(PgnErrorKind::InvalidMove(e)))?;
+                *current = current.next(from, m);
+                moves.push((from, m));
+            }
+            '.' => {
+                cursor.bump();
+            }
+            c => return Err(located(PgnErrorKind::UnexpectedCharacter(c))),
+        }
+    }
+
+    if variation_depth > 0 {
+        return Err(cursor.error(PgnErrorKind::UnbalancedVariation));
+    }
+    if !tags.is_empty() || !moves.is_empty() {
+        games.push(finish_game(
+            &mut tags, &mut moves, &mut state, fen_tag, "*",
+        )?);
+    }
+
+    Ok(games)
+}
+
+/// The position the game starts from, as the `Variant`, `FEN` and `SetUp` tags describe it.
+fn tagged_start(tags: &[(String, String)], fen_tag: (usize, usize)) -> Result<GameState, PgnError> {
+    let variant = tagged_variant(tags);
+    let mut start = match tags.iter().find(|(tag, _)| tag == "FEN") {
+        Some((_, fen)) => GameState::from_fen_with_variant(fen, variant).map_err(|e| PgnError {
+            line: fen_tag.0,
+            column: fen_tag.1,
+            kind: PgnErrorKind::InvalidFen(e),
+        })?,
+        None => GameState::with_variant(variant),
+    };
+    if tags
+        .iter()
+        .any(|(tag, variant)| tag == "Variant" && is_chess960_variant(variant))
+    {
+        start = start.into_chess960();
+    }
+    Ok(start)
+}
+
+fn finish_game(
+    tags: &mut Vec<(String, String)>,
+    moves: &mut Vec<(Position, Move)>,
+    state: &mut Option<GameState>,
+    fen_tag: (usize, usize),
+    result: &str,
+) -> Result<PgnGame, PgnError> {
+    let start = match state.take() {
+        Some(final_state) => final_state.initial_state(),
+        None => tagged_start(tags, fen_tag)?,
+    };
+
+    Ok(PgnGame {
+        tags: std::mem::take(tags),
+        moves: std::mem::take(moves),
+        result: result.to_string(),
+        start,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::fen::FenError;
+    use crate::state::game::{
+        DrawReason, GamePhase, GameState, Interaction, Move, PlayerColor, Position, WinReason,
+    };
+    use crate::state::pgn::{parse_pgn, PgnError, PgnErrorKind, PgnTags};
+    use crate::state::san::SanError;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// Where reading `pgn` failed and why.
+    fn error(pgn: &str) -> (usize, usize, PgnErrorKind) {
+        let PgnError { line, column, kind } = parse_pgn(pgn).unwrap_err();
+        (line, column, kind)
+    }
+
+    #[test]
+    fn tags() {
+        let pgn = r#"[Event "The \"Immortal\" Game"]
+[Site "C:\\London"]
+[  White   "Anderssen" ]
+
+1. e4 *"#;
+        let games = parse_pgn(pgn).unwrap();
+        assert_eq!(games.len(), 1);
+        let game = &games[0];
+        assert_eq!(game.tag("Event"), Some("The \"Immortal\" Game"));
+        assert_eq!(game.tag("Site"), Some("C:\\London"));
+        assert_eq!(game.tag("White"), Some("Anderssen"));
+        assert_eq!(game.tag("Black"), None);
+        assert_eq!(game.moves.len(), 1);
+    }
+
+    #[test]
+    fn tag_order() {
+        // the variant decides how the FEN is read, wherever the tags stand
+        let fen = "[FEN \"4k3/8/8/8/8/8/8/4K3[Q] w - - 0 1\"]";
+        let variant = "[Variant \"Crazyhouse\"]";
+        for tags in [[fen, variant], [variant, fen]] {
+            let pgn = format!("{}\n{}\n\n1. Q@e2+ *", tags[0], tags[1]);
+            let game = &parse_pgn(&pgn).unwrap()[0];
+            let state = game.final_state();
+            assert_eq!(state.variant().name(), "Crazyhouse");
+            assert_eq!(state.to_fen(), "4k3/8/8/8/8/8/4Q3/4K3[] b - - 1 1");
+        }
+
+        let game = &parse_pgn("[Variant \"Chess960\"]\n\n1. e4 *").unwrap()[0];
+        assert!(game.final_state().is_chess960());
+        assert!(game.state_at(0).is_chess960());
+    }
+
+    #[test]
+    fn skipped_text() {
+        let pgn = "% an escaped line, 1. e4\n\
+            1. e4 {a comment, 1. d4} e5 ; to the end of the line 2. d4\n\
+            2. Nf3 $1 (2. Bc4 Nc6 (2... Nf6) 3. Qh5) 2... Nc6! 3. Bb5?! a6 *";
+        let game = &parse_pgn(pgn).unwrap()[0];
+        let state = game.final_state();
+        assert_eq!(
+            state.to_fen(),
+            "r1bqkbnr/1ppp1ppp/p1n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 0 4"
+        );
+        assert_eq!(game.result, "*");
+    }
+
+    #[test]
+    fn results() {
+        let pgn = "[Termination \"White won by timeout\"]\n1. e4 e5 1-0\n\
+            1. f3 e5 2. g4 Qh4# 0-1\n\
+            1. d4 1/2-1/2\n\
+            1. c4 *";
+        let games = parse_pgn(pgn).unwrap();
+        let results = games.iter().map(|game| game.result.as_str());
+        assert_eq!(results.collect::<Vec<_>>(), ["1-0", "0-1", "1/2-1/2", "*"]);
+
+        let phases = games.iter().map(|game| game.final_state().phase());
+        let phases = phases.collect::<Vec<_>>();
+        assert!(matches!(
+            phases[0],
+            GamePhase::Won(PlayerColor::White, WinReason::Timeout)
+        ));
+        assert!(matches!(
+            phases[1],
+            GamePhase::Won(PlayerColor::Black, WinReason::Checkmate)
+        ));
+        assert!(matches!(phases[2], GamePhase::Draw(DrawReason::Agreement)));
+        assert!(matches!(phases[3], GamePhase::Turn(PlayerColor::Black)));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("[Event \"x\"]\n1. e4 e5 2. Ke3 *"),
+            (
+                2,
+                13,
+                PgnErrorKind::InvalidMove(SanError::Illegal("Ke3".into()))
+            )
+        );
+        assert_eq!(
+            error("1. e4 e5\n  2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# Ke7 *"),
+            (2, 34, PgnErrorKind::GameOver("Ke7".into()))
+        );
+        assert_eq!(
+            error("[FEN \"8/8/8/8/8/8/8/8 w - - 0 1\"]"),
+            (
+                1,
+                1,
+                PgnErrorKind::InvalidFen(FenError::MissingKing(PlayerColor::White))
+            )
+        );
+        assert_eq!(
+            error("[Event \"x\" *"),
+            (1, 1, PgnErrorKind::UnterminatedTag)
+        );
+        assert_eq!(
+            error("[Event \"x]\n"),
+            (1, 1, PgnErrorKind::UnterminatedString)
+        );
+        assert_eq!(
+            error("1. e4 {never closed"),
+            (1, 7, PgnErrorKind::UnterminatedComment)
+        );
+        assert_eq!(
+            error("1. e4 (1. d4 *"),
+            (1, 15, PgnErrorKind::UnbalancedVariation)
+        );
+        assert_eq!(
+            error("1. e4 ) *"),
+            (1, 7, PgnErrorKind::UnbalancedVariation)
+        );
+        assert_eq!(
+            error("1. e4 & *"),
+            (1, 7, PgnErrorKind::UnexpectedCharacter('&'))
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut state = GameState::new();
+        for san in ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "O-O", "Nf6"] {
+            let (from, m) = state.parse_san(san).unwrap();
+            state = state.apply(from, m).unwrap();
+        }
+        let tags = PgnTags {
+            event: "A \"test\"".to_string(),
+            ..PgnTags::default()
+        };
+        let pgn = state.to_pgn(&tags);
+        let game = &parse_pgn(&pgn).unwrap()[0];
+        assert_eq!(game.tag("Event"), Some("A \"test\""));
+        assert_eq!(game.moves, state.history());
+        assert_eq!(game.final_state().to_pgn(&tags), pgn);
+    }
+
+    fn play(mut state: GameState, moves: &[(&str, &str)]) -> GameState {
+        for (from, to) in moves {
+            let from = Position::from_algebraic(from).unwrap();
+            let to = Position::from_algebraic(to).unwrap();
+            state = state.next(from, Move::Move(to));
+        }
+        state
+    }
+
+    #[test]
+    fn export() {
+        let moves = [("f2", "f3"), ("e7", "e5"), ("g2", "g4"), ("d8", "h4")];
+        let state = play(GameState::new(), &moves);
+        let tags = PgnTags {
+            white: "A \"fool\"".to_string(),
+            ..PgnTags::default()
+        };
+        let tags = tags.dated(UNIX_EPOCH + Duration::from_secs(951_868_800));
+        assert_eq!(
+            state.to_pgn(&tags),
+            "[Event \"?\"]\n[Site \"?\"]\n[Date \"2000.03.01\"]\n[Round \"?\"]\n\
+            [White \"A \\\"fool\\\"\"]\n[Black \"?\"]\n[Result \"0-1\"]\n\
+            [Termination \"Black won by checkmate\"]\n\n\
+            1. f3 e5 2. g4 Qh4# 0-1\n"
+        );
+
+        // a game set up from a position says so, and black may move first
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 b - - 0 12";
+        let state = play(GameState::from_fen(fen).unwrap(), &[("e8", "d8")]);
+        let pgn = state.to_pgn(&PgnTags::default());
+        assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 12\"]\n"));
+        assert!(pgn.ends_with("\n\n12... Kd8 *\n"));
+
+        // how the game ended comes back when it is read again
+        let resigned = GameState::new().interact(Interaction::Resign);
+        let pgn = resigned.to_pgn(&PgnTags::default());
+        assert!(pgn.contains("[Termination \"Black won by resignation\"]\n"));
+        let phase = parse_pgn(&pgn).unwrap()[0].final_state().phase();
+        assert!(matches!(
+            phase,
+            GamePhase::Won(PlayerColor::Black, WinReason::Resignation)
+        ));
+    }
+}
@@ -0,0 +1,431 @@
+use crate::state::board::PieceKind;
+use crate::state::game::{CastleType, GamePhase, GameState, Move, Piece, Position, WinReason};
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SanError {
+    Empty,
+    Malformed(String),
+    InvalidPromotion(String),
+    Illegal(String),
+    Ambiguous(String),
+}
+
+impl Display for SanError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SanError::Empty => write!(f, "empty move"),
+            SanError::Malformed(san) => write!(f, "malformed move '{}'", san),
+            SanError::InvalidPromotion(san) => write!(f, "invalid promotion in '{}'", san),
+            SanError::Illegal(san) => write!(f, "illegal move '{}'", san),
+            SanError::Ambiguous(san) => write!(f, "ambiguous move '{}'", san),
+        }
+    }
+}
+
+impl std::error::Error for SanError {}
+
+fn piece_letter(kind: PieceKind) -> Option<char> {
+    match kind {
+        PieceKind::Pawn => None,
+        PieceKind::Rook => Some('R'),
+        PieceKind::Knight => Some('N'),
+        PieceKind::Bishop => Some('B'),
+        PieceKind::King => Some('K'),
+        PieceKind::Queen => Some('Q'),
+    }
+}
+
+fn drop_letter(kind: PieceKind) -> char {
+    piece_letter(kind).unwrap_or('P')
+}
+
+fn piece_from_letter(letter: char) -> Option<PieceKind> {
+    match letter {
+        'P' => Some(PieceKind::Pawn),
+        'R' => Some(PieceKind::Rook),
+        'N' => Some(PieceKind::Knight),
+        'B' => Some(PieceKind::Bishop),
+        'K' => Some(PieceKind::King),
+        'Q' => Some(PieceKind::Queen),
+        _ => None,
+    }
+}
+
+impl GameState {
+    /// Describes a legal move of the side to move in Standard Algebraic Notation.
+    /// Drops are written like `N@f3`, pawn drops as `P@e4`.
+    pub fn to_san(&self, from: Position, m: Move) -> String {
+        let piece = match (m, self.piece_at(from)) {
+            (Move::Drop(kind, _), _) => Piece {
+                kind,
+                color: self.active_color,
+            },
+            (_, Some(piece)) => *piece,
+            (_, None) => return String::new(),
+        };
+
+        let mut san = match m {
+            Move::Castle(CastleType::Short) => "O-O".to_string(),
+            Move::Castle(CastleType::Long) => "O-O-O".to_string(),
+            Move::Drop(kind, to) => format!("{}@{}", drop_letter(kind), to),
+            _ => {
+                let to = m.target(piece.color);
+                let capture = match m {
+                    Move::Take(..) => true,
+                    Move::Promote(to, _) => self.piece_at(to).is_some(),
+                    _ => false,
+                };
+
+                let mut san = String::new();
+                match piece_letter(piece.kind) {
+                    Some(letter) => {
+                        san.push(letter);
+                        san.push_str(&self.disambiguation(from, to));
+                    }
+                    None if capture => san.push(from.file_char()),
+                    None => {}
+                }
+                if capture {
+                    san.push('x');
+                }
+                san.push_str(&to.to_string());
+                if let Move::Promote(_, promoted) = m {
+                    san.push('=');
+                    san.push(piece_letter(promoted.kind).unwrap_or('Q'));
+                }
+                san
+            }
+        };
+
+        let after = self.next(from, m);
+        if let GamePhase::Won(_, WinReason::Checkmate | WinReason::Explosion) = after.phase() {
+            san.push('#');
+        } else if after.is_in_check() {
+            san.push('+');
+        }
+
+        san
+    }
+
+    /// Describes a move in pure coordinate notation, e.g. `e2e4` or `e7e8q`, as used by engines.
+    /// Chess960 castling is written as the king taking its own rook, e.g. `b1a1`, and drops
+    /// like in SAN, e.g. `N@f3`.
+    pub fn to_coordinate_notation(&self, from: Position, m: Move) -> String {
+        if let Move::Drop(kind, to) = m {
+            return format!("{}@{}", drop_letter(kind), to);
+        }
+        let to = match m {
+            Move::Castle(side) if self.chess960 => self.castling_rook(self.active_color(), side),
+            _ => m.target(self.active_color()),
+        };
+        let mut notation = format!("{}{}", from, to);
+        if let Move::Promote(_, promoted) = m {
+            notation.extend(piece_letter(promoted.kind).map(|c| c.to_ascii_lowercase()));
+        }
+        notation
+    }
+
+    /// The moves played since [GameState::initial_fen] in coordinate notation.
+    pub fn history_in_coordinate_notation(&self) -> Vec<String> {
+        let mut line = self.initial_state();
+        let mut notation = vec![];
+        for (from, m) in &self.history {
+            notation.push(line.to_coordinate_notation(*from, *m));
+            line = line.next(*from, *m);
+        }
+        notation
+    }
+
+    /// Finds the legal move of the side to move described in pure coordinate notation,
+    /// the counterpart of [GameState::to_coordinate_notation].
+    pub fn parse_coordinate_notation(&self, notation: &str) -> Result<(Position, Move), SanError> {
+        let malformed = || SanError::Malformed(notation.to_string());
+        if !notation.is_ascii() || !(4..=5).contains(&notation.len()) {
+            return Err(malformed());
+        }
+        if notation.contains('@') {
+            return self.parse_drop(notation);
+        }
+        let from = Position::from_algebraic(&notation[..2]).ok_or_else(malformed)?;
+        let to = Position::from_algebraic(&notation[2..4]).ok_or_else(malformed)?;
+        let promotion = match notation[4..].chars().next() {
+            Some(letter) => match piece_from_letter(letter.to_ascii_uppercase()) {
+                Some(PieceKind::Pawn) | Some(PieceKind::King) | None => {
+                    return Err(SanError::InvalidPromotion(notation.to_string()))
+                }
+                promoted => promoted,
+            },
+            None => None,
+        };
+
+        let player = self.active_color();
+        self.legal_moves()
+            .into_iter()
+            .find(|(start, m)| {
+                *start == from
+                    && match m {
+                        Move::Promote(target, promoted) => {
+                            *target == to && Some(promoted.kind) == promotion
+                        }
+                        // king onto rook is understood in standard chess too
+                        Move::Castle(side) => {
+                            promotion.is_none()
+                                && (to == self.castling_rook(player, *side)
+                                    || (to == m.target(player) && !self.chess960))
+                        }
+                        _ => m.target(player) == to && promotion.is_none(),
+                    }
+            })
+            .ok_or_else(|| SanError::Illegal(notation.to_string()))
+    }
+
+    /// Finds the legal move of the side to move described in Standard Algebraic Notation.
+    ///
+    /// Besides strict SAN this accepts the usual sloppy spellings: `0-0`, promotions
+    /// without `=`, long algebraic (`Ng1f3`, `e2-e4`) and trailing annotations (`e4!?`).
+    pub fn parse_san(&self, san: &str) -> Result<(Position, Move), SanError> {
+        let illegal = || SanError::Illegal(san.to_string());
+        let malformed = || SanError::Malformed(san.to_string());
+
+        let body = san
+            .trim()
+            .trim_end_matches(['+', '#', '!', '?'])
+            .trim_end_matches("e.p.")
+            .trim_end();
+        if body.is_empty() {
+            return Err(SanError::Empty);
+        }
+        if body.contains('@') {
+            return self.parse_drop(body).map_err(|e| match e {
+                SanError::Malformed(_) => malformed(),
+                _ => illegal(),
+            });
+        }
+        let player = self.active_color();
+
+        let castle = match body {
+            "O-O" | "0-0" => Some(CastleType::Short),
+            "O-O-O" | "0-0-0" => Some(CastleType::Long),
+            _ => None,
+        };
+
+        let mut body = body.replace(['x', ':', '-'], "");
+        let kind = match body.chars().next().and_then(piece_from_letter) {
+            Some(kind) if castle.is_none() => {
+                body.remove(0);
+                kind
+            }
+            _ => PieceKind::Pawn,
+        };
+
+        let mut promotion = None;
+        if kind == PieceKind::Pawn && castle.is_none() && body.len() > 2 {
+            if let Some(letter) = body.chars().last().filter(char::is_ascii_uppercase) {
+                promotion = match piece_from_letter(letter) {
+                    Some(PieceKind::Pawn) | Some(PieceKind::King) | None => {
+                        return Err(SanError::InvalidPromotion(san.to_string()))
+                    }
+                    promoted => promoted,
+                };
+                body.pop();
+                if body.ends_with('=') {
+                    body.pop();
+                }
+            }
+        }
+
+        let (target, from_file, from_rank) = if castle.is_some() {
+            (None, None, None)
+        } else {
+            if body.len() < 2 || body.len() > 4 || !body.is_ascii() {
+                return Err(malformed());
+            }
+            let target = Position::from_algebraic(&body[body.len() - 2..]).ok_or_else(malformed)?;
+            let mut from_file = None;
+            let mut from_rank = None;
+            for c in body[..body.len() - 2].chars() {
+                match c {
+                    'a'..='h' => from_file = Some(c as i32 - 'a' as i32),
+                    '1'..='8' => from_rank = Some(7 - (c as i32 - '1' as i32)),
+                    _ => return Err(malformed()),
+                }
+            }
+            (Some(target), from_file, from_rank)
+        };
+
+        let candidates = self
+            .legal_moves()
+            .into_iter()
+            .filter(|(from, m)| {
+                let kind_matches = self.piece_at(*from).map(|p| p.kind) == Some(kind);
+                match (castle, m) {
+                    (Some(castle), Move::Castle(side)) => castle == *side,
+                    (Some(_), _) => false,
+                    (None, _)
+                        if !kind_matches
+                            || from_file.is_some_and(|file| file != from.0)
+                            || from_rank.is_some_and(|rank| rank != from.1) =>
+                    {
+                        false
+                    }
+                    (None, Move::Castle(_)) => false,
+                    (None, Move::Promote(to, promoted)) => {
+                        Some(*to) == target && Some(promoted.kind) == promotion
+                    }
+                    (None, m) => Some(m.target(player)) == target && promotion.is_none(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        match candidates.len() {
+            0 => Err(illegal()),
+            1 => Ok(candidates[0]),
+            _ => Err(SanError::Ambiguous(san.to_string())),
+        }
+    }
+
+    /// Finds the legal drop written like `N@f3`, a pawn drop with or without its `P`.
+    fn parse_drop(&self, notation: &str) -> Result<(Position, Move), SanError> {
+        let malformed = || SanError::Malformed(notation.to_string());
+        let (piece, square) = notation.split_once('@').ok_or_else(malformed)?;
+        let kind = match piece {
+            "" => PieceKind::Pawn,
+            letter => piece_from_letter(letter.chars().next().ok_or_else(malformed)?)
+                .filter(|_| letter.len() == 1)
+                .ok_or_else(malformed)?,
+        };
+        let to = Position::from_algebraic(square).ok_or_else(malformed)?;
+        let drop = (to, Move::Drop(kind, to));
+        if self.legal_moves_from(to).contains(&drop.1) {
+            Ok(drop)
+        } else {
+            Err(SanError::Illegal(notation.to_string()))
+        }
+    }
+
+    /// The file and/or rank needed to tell the piece on `from` apart from
+    /// identical pieces that could also reach `to`.
+    fn disambiguation(&self, from: Position, to: Position) -> String {
+        let piece = match self.piece_at(from) {
+            Some(piece) => *piece,
+            None => return String::new(),
+        };
+
+        let rivals = self
+            .legal_moves()
+            .into_iter()
+            .filter(|(other, m)| {
+                *other != from
+                    && self.piece_at(*other) == Some(&piece)
+                    && m.target(piece.color) == to
+            })
+            .map(|(other, _)| other)
+            .collect::<Vec<_>>();
+
+        if rivals.is_empty() {
+            String::new()
+        } else if rivals.iter().all(|other| other.0 != from.0) {
+            from.file_char().to_string()
+        } else if rivals.iter().all(|other| other.1 != from.1) {
+            from.rank_char().to_string()
+        } else {
+            from.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::game::GameState;
+    use crate::state::san::SanError;
+
+    /// The SAN written for `san` read in the position of `fen`.
+    fn round_trip(fen: &str, san: &str) -> Result<String, SanError> {
+        let state = GameState::from_fen(fen).unwrap();
+        let (from, m) = state.parse_san(san)?;
+        Ok(state.to_san(from, m))
+    }
+
+    #[test]
+    fn disambiguation() {
+        // rooks on a1 and h1 share a rank, rooks on a1 and a5 a file
+        let fen = "4k3/8/8/R7/8/8/7K/R6R w - - 0 1";
+        assert_eq!(round_trip(fen, "Rad1"), Ok("Rad1".into()));
+        assert_eq!(round_trip(fen, "Rhd1"), Ok("Rhd1".into()));
+        assert_eq!(round_trip(fen, "R1a3"), Ok("R1a3".into()));
+        assert_eq!(round_trip(fen, "R5a3"), Ok("R5a3".into()));
+        assert_eq!(
+            round_trip(fen, "Rd1"),
+            Err(SanError::Ambiguous("Rd1".into()))
+        );
+        assert_eq!(
+            round_trip(fen, "Ra3"),
+            Err(SanError::Ambiguous("Ra3".into()))
+        );
+
+        // three queens reaching e4 need the whole square for the one on h4
+        let fen = "5k2/8/8/8/Q6Q/8/8/4K2Q w - - 0 1";
+        assert_eq!(round_trip(fen, "Qh4e4"), Ok("Qh4e4".into()));
+        assert_eq!(round_trip(fen, "Qae4"), Ok("Qae4".into()));
+        assert_eq!(round_trip(fen, "Q1e4"), Ok("Q1e4".into()));
+        assert_eq!(
+            round_trip(fen, "Qhe4"),
+            Err(SanError::Ambiguous("Qhe4".into()))
+        );
+    }
+
+    #[test]
+    fn promotions() {
+        let fen = "1r2k3/2P5/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(round_trip(fen, "cxb8=N"), Ok("cxb8=N".into()));
+        assert_eq!(round_trip(fen, "cxb8=Q+"), Ok("cxb8=Q+".into()));
+        assert_eq!(round_trip(fen, "c8=R+"), Ok("c8=R+".into()));
+        assert_eq!(
+            round_trip(fen, "c8=K"),
+            Err(SanError::InvalidPromotion("c8=K".into()))
+        );
+        assert_eq!(round_trip(fen, "c8"), Err(SanError::Illegal("c8".into())));
+    }
+
+    #[test]
+    fn castling_and_checks() {
+        let fen = "r3k2r/p6p/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(round_trip(fen, "O-O"), Ok("O-O".into()));
+        assert_eq!(round_trip(fen, "O-O-O"), Ok("O-O-O".into()));
+
+        // the rook lands on f1 and gives check along the f-file
+        let fen = "5k2/8/8/8/8/8/8/4K2R w K - 0 1";
+        assert_eq!(round_trip(fen, "O-O+"), Ok("O-O+".into()));
+
+        // the king guards its queen next to the other king
+        let fen = "7k/Q7/6K1/8/8/8/8/8 w - - 0 1";
+        assert_eq!(round_trip(fen, "Qg7#"), Ok("Qg7#".into()));
+        assert_eq!(round_trip(fen, "Qg7"), Ok("Qg7#".into()));
+    }
+
+    #[test]
+    fn sloppy() {
+        let fen = "r3k2r/pP5p/8/8/8/8/8/R3K1NR w KQkq - 0 1";
+        assert_eq!(round_trip(fen, "0-0-0"), Ok("O-O-O".into()));
+        assert_eq!(round_trip(fen, "b8Q"), Ok("b8=Q+".into()));
+        assert_eq!(round_trip(fen, "Ng1f3"), Ok("Nf3".into()));
+        assert_eq!(round_trip(fen, "Ng1-f3!?"), Ok("Nf3".into()));
+
+        let fen = "4k3/8/8/8/8/8/8/4K1NR w K - 0 1";
+        assert_eq!(round_trip(fen, "0-0"), Err(SanError::Illegal("0-0".into())));
+        assert_eq!(round_trip(fen, "Nf3"), Ok("Nf3".into()));
+        assert_eq!(round_trip(fen, ""), Err(SanError::Empty));
+        assert_eq!(
+            round_trip(fen, "Nz9"),
+            Err(SanError::Malformed("Nz9".into()))
+        );
+
+        // en passant with its suffix, before or after the check
+        let fen = "8/2k5/8/3pP3/8/8/8/4K3 w - d6 0 1";
+        assert_eq!(round_trip(fen, "exd6"), Ok("exd6+".into()));
+        assert_eq!(round_trip(fen, "exd6e.p."), Ok("exd6+".into()));
+        assert_eq!(round_trip(fen, "exd6 e.p.+"), Ok("exd6+".into()));
+        assert_eq!(round_trip(fen, "exd6e.p.+"), Ok("exd6+".into()));
+    }
+}
@@ -0,0 +1,394 @@
+use crate::state::bitboard::{Bitboard, CENTER};
+use crate::state::board::PieceKind;
+use crate::state::fen::STARTING_FEN;
+use crate::state::game::{
+    DrawReason, GamePhase, GameState, Move, PlayerColor, Position, WinReason, POCKET_KINDS,
+};
+use std::fmt::Debug;
+
+const PROMOTIONS: [PieceKind; 4] = [
+    PieceKind::Queen,
+    PieceKind::Rook,
+    PieceKind::Knight,
+    PieceKind::Bishop,
+];
+
+/// The rules of a game played on a chess board. Every method defaults to standard chess,
+/// so a variant only overrides the rules it changes.
+pub trait Variant: Debug + Sync {
+    /// The name used in the PGN `Variant` tag.
+    fn name(&self) -> &'static str;
+
+    fn start_fen(&self) -> &'static str {
+        STARTING_FEN
+    }
+
+    /// Appends the legal moves of `color` for the pieces standing on `from`.
+    fn generate_moves(
+        &self,
+        state: &GameState,
+        color: PlayerColor,
+        from: Bitboard,
+        moves: &mut Vec<(Position, Move)>,
+    ) {
+        state.generate_piece_moves(color, from, moves, |from, m| {
+            self.is_legal(state, color, from, m)
+        });
+    }
+
+    /// Whether `color` may play `m` from `from`, a move that follows the movement rules.
+    fn is_legal(&self, state: &GameState, color: PlayerColor, from: Position, m: Move) -> bool {
+        state.leaves_king_safe(color, from, m)
+    }
+
+    /// How the game ended with the side to move in `state`, if it did.
+    fn game_end(&self, state: &GameState, has_legal_moves: bool) -> Option<GamePhase> {
+        if !has_legal_moves {
+            Some(mate_or_stalemate(state))
+        } else if state.is_dead_position() {
+            Some(GamePhase::Draw(DrawReason::InsufficientMaterial))
+        } else {
+            None
+        }
+    }
+
+    /// Whether `color` could still win by any series of legal moves, which decides
+    /// between a win and a draw when its opponent runs out of time.
+    fn can_win(&self, state: &GameState, color: PlayerColor) -> bool {
+        state.has_mating_material(color)
+    }
+
+    /// What a pawn may turn into on the last rank.
+    fn promotions(&self) -> &'static [PieceKind] {
+        &PROMOTIONS
+    }
+
+    /// Whether captured pieces go to the capturer's pocket, to be dropped back onto the board.
+    fn has_drops(&self) -> bool {
+        false
+    }
+
+    /// Whether the checks each side gave are counted, they are part of the position then.
+    fn counts_checks(&self) -> bool {
+        false
+    }
+
+    /// Whether a capture blows up the capturer and every piece but a pawn next to it.
+    fn explodes(&self) -> bool {
+        false
+    }
+
+    /// Anything the status area should show besides whose turn it is, like a count the variant keeps.
+    fn status(&self, _state: &GameState) -> Option<String> {
+        None
+    }
+}
+
+/// How the game ends for the side to move when it has no legal moves.
+fn mate_or_stalemate(state: &GameState) -> GamePhase {
+    if state.is_in_check() {
+        GamePhase::Won(state.active_color().opponent(), WinReason::Checkmate)
+    } else {
+        GamePhase::Draw(DrawReason::Stalemate)
+    }
+}
+
+#[derive(Debug)]
+pub struct Standard;
+
+impl Variant for Standard {
+    fn name(&self) -> &'static str {
+        "Standard"
+    }
+}
+
+/// A king reaching one of the four center squares wins.
+#[derive(Debug)]
+pub struct KingOfTheHill;
+
+impl Variant for KingOfTheHill {
+    fn name(&self) -> &'static str {
+        "King of the Hill"
+    }
+
+    fn game_end(&self, state: &GameState, has_legal_moves: bool) -> Option<GamePhase> {
+        let mover = state.active_color().opponent();
+        if state.board().pieces(PieceKind::King, mover) & CENTER != 0 {
+            Some(GamePhase::Won(mover, WinReason::KingOfTheHill))
+        } else if !has_legal_moves {
+            Some(mate_or_stalemate(state))
+        } else {
+            // a lone king can still walk up the hill, so no material is insufficient
+            None
+        }
+    }
+
+    fn can_win(&self, _state: &GameState, _color: PlayerColor) -> bool {
+        true
+    }
+}
+
+/// Giving check for the third time wins.
+#[derive(Debug)]
+pub struct ThreeCheck;
+
+impl Variant for ThreeCheck {
+    fn name(&self) -> &'static str {
+        "Three-check"
+    }
+
+    fn game_end(&self, state: &GameState, has_legal_moves: bool) -> Option<GamePhase> {
+        let mover = state.active_color().opponent();
+        let board = state.board();
+        if state.checks_given(mover) >= 3 {
+            Some(GamePhase::Won(mover, WinReason::ThreeChecks))
+        } else if !has_legal_moves {
+            Some(mate_or_stalemate(state))
+        } else if board.occupied() == board.kind(PieceKind::King) {
+            // any other piece can still give check
+            Some(GamePhase::Draw(DrawReason::InsufficientMaterial))
+        } else {
+            None
+        }
+    }
+
+    fn can_win(&self, state: &GameState, color: PlayerColor) -> bool {
+        let board = state.board();
+        board.color(color) & !board.kind(PieceKind::King) != 0
+    }
+
+    fn counts_checks(&self) -> bool {
+        true
+    }
+
+    fn status(&self, state: &GameState) -> Option<String> {
+        Some(format!(
+            "Checks: {}-{}",
+            state.checks_given(PlayerColor::White),
+            state.checks_given(PlayerColor::Black)
+        ))
+    }
+}
+
+/// Captured pieces change sides and can be dropped back onto the board instead of moving.
+#[derive(Debug)]
+pub struct Crazyhouse;
+
+impl Variant for Crazyhouse {
+    fn name(&self) -> &'static str {
+        "Crazyhouse"
+    }
+
+    fn start_fen(&self) -> &'static str {
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w KQkq - 0 1"
+    }
+
+    fn generate_moves(
+        &self,
+        state: &GameState,
+        color: PlayerColor,
+        from: Bitboard,
+        moves: &mut Vec<(Position, Move)>,
+    ) {
+        let legal = |from, m| self.is_legal(state, color, from, m);
+        state.generate_piece_moves(color, from, moves, legal);
+        state.generate_drops(color, from, moves, legal);
+    }
+
+    fn game_end(&self, state: &GameState, has_legal_moves: bool) -> Option<GamePhase> {
+        // whatever was captured can come back, so no material is insufficient
+        (!has_legal_moves).then(|| mate_or_stalemate(state))
+    }
+
+    fn can_win(&self, state: &GameState, _color: PlayerColor) -> bool {
+        // any piece on the board can be captured and dropped by either side
+        let board = state.board();
+        let mut pockets = [PlayerColor::White, PlayerColor::Black]
+            .into_iter()
+            .flat_map(|color| POCKET_KINDS.map(|kind| state.pocket(color, kind)));
+        board.occupied() != board.kind(PieceKind::King) || pockets.any(|count| count > 0)
+    }
+
+    fn has_drops(&self) -> bool {
+        true
+    }
+}
+
+/// A capture blows up both pieces and every piece but a pawn next to them, and blowing up
+/// the enemy king wins.
+#[derive(Debug)]
+pub struct Atomic;
+
+impl Variant for Atomic {
+    fn name(&self) -> &'static str {
+        "Atomic"
+    }
+
+    fn is_legal(&self, state: &GameState, color: PlayerColor, from: Position, m: Move) -> bool {
+        state.leaves_king_safe_atomic(color, from, m)
+    }
+
+    fn game_end(&self, state: &GameState, has_legal_moves: bool) -> Option<GamePhase> {
+        let mover = state.active_color().opponent();
+        let board = state.board();
+        if board.pieces(PieceKind::King, state.active_color()) == 0 {
+            Some(GamePhase::Won(mover, WinReason::Explosion))
+        } else if !has_legal_moves {
+            Some(mate_or_stalemate(state))
+        } else if board.occupied() == board.kind(PieceKind::King) {
+            // kings cannot capture, so nothing can explode any more
+            Some(GamePhase::Draw(DrawReason::InsufficientMaterial))
+        } else {
+            None
+        }
+    }
+
+    fn explodes(&self) -> bool {
+        true
+    }
+}
+
+/// Every variant that can be played.
+pub const VARIANTS: [&dyn Variant; 5] =
+    [&Standard, &KingOfTheHill, &ThreeCheck, &Crazyhouse, &Atomic];
+
+/// Looks a variant up by its name, ignoring case, spaces and dashes, e.g. `kingofthehill`.
+pub fn variant_by_name(name: &str) -> Option<&'static dyn Variant> {
+    let simplify = |name: &str| {
+        name.chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_lowercase()
+    };
+    VARIANTS
+        .into_iter()
+        .find(|variant| simplify(variant.name()) == simplify(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::board::PieceKind;
+    use crate::state::game::{
+        DrawReason, GamePhase, GameState, Move, PlayerColor, Position, WinReason,
+    };
+    use crate::state::pgn::PgnTags;
+    use crate::state::variant::{variant_by_name, KingOfTheHill, Standard, ThreeCheck, Variant};
+
+    /// A variant from a set up position where pawns only promote to knights.
+    #[derive(Debug)]
+    struct Knights;
+
+    impl Variant for Knights {
+        fn name(&self) -> &'static str {
+            "Knights"
+        }
+
+        fn start_fen(&self) -> &'static str {
+            "4k3/P7/8/8/8/8/8/4K3 w - - 0 1"
+        }
+
+        fn promotions(&self) -> &'static [PieceKind] {
+            &[PieceKind::Knight]
+        }
+    }
+
+    fn play(mut state: GameState, moves: &[&str]) -> GameState {
+        for san in moves {
+            let (from, m) = state.parse_san(san).unwrap();
+            state = state.apply(from, m).unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn by_name() {
+        assert_eq!(variant_by_name(" standard ").unwrap().name(), "Standard");
+        assert_eq!(
+            variant_by_name("kingofthehill").unwrap().name(),
+            "King of the Hill"
+        );
+        assert_eq!(
+            variant_by_name("Three check").unwrap().name(),
+            "Three-check"
+        );
+        assert!(variant_by_name("Shogi").is_none());
+    }
+
+    #[test]
+    fn rules_come_from_the_variant() {
+        let a7 = Position::from_algebraic("a7").unwrap();
+        let promotions = |state: &GameState| {
+            state
+                .legal_moves_from(a7)
+                .into_iter()
+                .filter_map(|m| match m {
+                    Move::Promote(_, piece) => Some(piece.kind),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let state = GameState::with_variant(&Knights);
+        assert_eq!(state.to_fen(), Knights.start_fen());
+        assert_eq!(promotions(&state), [PieceKind::Knight]);
+        let standard = GameState::from_fen_with_variant(Knights.start_fen(), &Standard).unwrap();
+        assert_eq!(promotions(&standard).len(), 4);
+
+        // the variant's start position needs no FEN tag, the variant is named instead
+        let pgn = state.to_pgn(&PgnTags::default());
+        assert!(pgn.contains("[Variant \"Knights\"]\n"));
+        assert!(!pgn.contains("[SetUp"));
+        let pgn = standard.to_pgn(&PgnTags::default());
+        assert!(!pgn.contains("[Variant"));
+        assert!(pgn.contains("[SetUp \"1\"]\n"));
+    }
+
+    #[test]
+    fn king_of_the_hill() {
+        let fen = "4k3/8/8/8/8/4K3/8/8 w - - 0 1";
+        let state = GameState::from_fen_with_variant(fen, &KingOfTheHill).unwrap();
+        // bare kings are no draw, either of them may still reach the center
+        assert!(matches!(state.phase(), GamePhase::Turn(PlayerColor::White)));
+        let state = play(state, &["Kd4"]);
+        assert!(matches!(
+            state.phase(),
+            GamePhase::Won(PlayerColor::White, WinReason::KingOfTheHill)
+        ));
+
+        let state = GameState::from_fen(fen).unwrap();
+        assert!(matches!(
+            state.phase(),
+            GamePhase::Draw(DrawReason::InsufficientMaterial)
+        ));
+    }
+
+    #[test]
+    fn three_check() {
+        let fen = "4k3/8/8/8/8/8/8/R3K3 w - - 0 1";
+        let state = GameState::from_fen_with_variant(fen, &ThreeCheck).unwrap();
+        let state = play(state, &["Ra8+", "Kd7", "Ra7+", "Kd6"]);
+        assert_eq!(state.checks_given(PlayerColor::White), 2);
+        assert_eq!(state.checks_given(PlayerColor::Black), 0);
+        assert_eq!(ThreeCheck.status(&state).unwrap(), "Checks: 2-0");
+
+        let won = play(state.clone(), &["Ra6+"]);
+        assert!(matches!(
+            won.phase(),
+            GamePhase::Won(PlayerColor::White, WinReason::ThreeChecks)
+        ));
+        // taking the check back takes its count back too
+        let undone = won.undo();
+        assert_eq!(undone.checks_given(PlayerColor::White), 2);
+        assert!(matches!(
+            undone.phase(),
+            GamePhase::Turn(PlayerColor::White)
+        ));
+
+        let kings = "4k3/8/8/8/8/8/8/4K3 w - - 0 1";
+        let state = GameState::from_fen_with_variant(kings, &ThreeCheck).unwrap();
+        assert!(matches!(
+            state.phase(),
+            GamePhase::Draw(DrawReason::InsufficientMaterial)
+        ));
+    }
+}
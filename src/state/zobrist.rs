@@ -0,0 +1,134 @@
+use crate::state::bitboard::{color_index, kind_index, pawn_attacks, squares, Board};
+use crate::state::board::PieceKind;
+use crate::state::game::{GameState, Piece, PlayerColor};
+use std::sync::OnceLock;
+
+struct Keys {
+    pieces: [[[u64; 64]; 6]; 2],
+    castling: [u64; 4],
+    en_passant: [u64; 8],
+    black_to_move: u64,
+    /// By color, kind and how many of that kind are in the pocket.
+    pockets: [[[u64; POCKET_KEYS]; 6]; 2],
+    /// By color and how many checks it gave, in variants that count them.
+    checks: [[u64; CHECK_KEYS]; 2],
+}
+
+/// A pocket holds at most the 16 pawns of a kind, an empty one has no key.
+const POCKET_KEYS: usize = 17;
+/// The third check ends the game, no check given yet has no key.
+const CHECK_KEYS: usize = 4;
+
+fn keys() -> &'static Keys {
+    static KEYS: OnceLock<Keys> = OnceLock::new();
+    KEYS.get_or_init(Keys::new)
+}
+
+impl Keys {
+    fn new() -> Self {
+        // splitmix64 with a fixed seed, so keys stay the same between runs and can be stored
+        let mut seed: u64 = 0x0123_4567_89ab_cdef;
+        let mut next = || {
+            seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+
+        let mut keys = Keys {
+            pieces: [[[0; 64]; 6]; 2],
+            castling: [0; 4],
+            en_passant: [0; 8],
+            black_to_move: 0,
+            pockets: [[[0; POCKET_KEYS]; 6]; 2],
+            checks: [[0; CHECK_KEYS]; 2],
+        };
+        for color in keys.pieces.iter_mut() {
+            for kind in color.iter_mut() {
+                kind.iter_mut().for_each(|key| *key = next());
+            }
+        }
+        keys.castling.iter_mut().for_each(|key| *key = next());
+        keys.en_passant.iter_mut().for_each(|key| *key = next());
+        keys.black_to_move = next();
+        for color in keys.pockets.iter_mut() {
+            for kind in color.iter_mut() {
+                kind[1..].iter_mut().for_each(|key| *key = next());
+            }
+        }
+        for color in keys.checks.iter_mut() {
+            color[1..].iter_mut().for_each(|key| *key = next());
+        }
+        keys
+    }
+}
+
+/// The key of `piece` standing on `square`.
+pub fn piece_key(piece: Piece, square: usize) -> u64 {
+    keys().pieces[color_index(piece.color)][kind_index(piece.kind)][square]
+}
+
+fn board_key(board: &Board) -> u64 {
+    squares(board.occupied())
+        .filter_map(|square| board.get(square).map(|piece| piece_key(*piece, square)))
+        .fold(0, |key, piece| key ^ piece)
+}
+
+impl GameState {
+    /// The Zobrist key of the position: pieces, side to move, castling rights, en passant,
+    /// pockets and checks given, so equal keys mean the same position in the sense of the repetition rules.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Computes the key from scratch, [GameState::make_move] keeps it up to date afterwards.
+    pub(super) fn compute_hash(&self) -> u64 {
+        board_key(&self.board) ^ self.state_key()
+    }
+
+    /// Everything in the key besides the pieces.
+    pub(super) fn state_key(&self) -> u64 {
+        let keys = keys();
+        let mut key = 0;
+
+        let rights = [
+            self.castling_white.short_side_available,
+            self.castling_white.long_side_available,
+            self.castling_black.short_side_available,
+            self.castling_black.long_side_available,
+        ];
+        for (available, castling) in rights.iter().zip(keys.castling) {
+            if *available {
+                key ^= castling;
+            }
+        }
+
+        // only an en passant capture that can actually be played changes the position
+        if let Some(pawn) = self.previous_en_passe_move {
+            let target = match self.active_color {
+                PlayerColor::Black => pawn.idx() + 8,
+                PlayerColor::White => pawn.idx() - 8,
+            };
+            let capturers = self.board.pieces(PieceKind::Pawn, self.active_color);
+            if pawn_attacks(self.active_color.opponent(), target) & capturers != 0 {
+                key ^= keys.en_passant[pawn.0 as usize];
+            }
+        }
+
+        if self.active_color == PlayerColor::Black {
+            key ^= keys.black_to_move;
+        }
+
+        for (pockets, keys) in self.pockets.iter().zip(&keys.pockets) {
+            for (count, keys) in pockets.iter().zip(keys) {
+                key ^= keys[(*count as usize).min(POCKET_KEYS - 1)];
+            }
+        }
+
+        for (checks, keys) in self.checks.iter().zip(&keys.checks) {
+            key ^= keys[(*checks as usize).min(CHECK_KEYS - 1)];
+        }
+        key
+    }
+}
@@ -1,30 +1,41 @@
+use crate::state::bitboard::square_bit;
 use crate::state::board::PieceKind;
-use crate::state::game::{GameState, Interaction, Move, PlayerColor, Position};
+use crate::state::game::{
+    GameState, Interaction, Move, Piece, PlayerColor, Position, POCKET_KINDS,
+};
 use crate::ui::text::FontRenderer;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::{BlendMode, WindowCanvas};
 use std::cmp::min;
+use std::time::Duration;
 
 const BOARD_SIZE: i32 = 8;
 pub const COLOR_WHITE: Color = Color::RGB(223, 158, 69);
 pub const COLOR_BLACK: Color = Color::RGB(140, 36, 11);
 pub const CLEAR_COLOR: Color = Color::RGB(192, 192, 192);
 pub const SELECTED_HIGHLIGHT_COLOR: Color = Color::RGBA(255, 255, 255, 50);
+const EXPLOSION_COLOR: Color = Color::RGB(255, 140, 0);
+/// How long the squares blown up in atomic chess take to fade.
+const EXPLOSION_DURATION: Duration = Duration::from_millis(700);
 
 pub struct BoardRenderer<'ttf_module, 'rwops> {
     screen_area: Rect,
     font_renderer: &'ttf_module FontRenderer<'ttf_module, 'rwops>,
+    /// For the piece counts next to the pockets.
+    text_renderer: &'ttf_module FontRenderer<'ttf_module, 'rwops>,
 }
 
 impl<'ttf_module, 'rwops> BoardRenderer<'ttf_module, 'rwops> {
     pub fn new(
         screen_area: Rect,
+        font_renderer: &'ttf_module FontRenderer<'ttf_module, 'rwops>,
         text_renderer: &'ttf_module FontRenderer<'ttf_module, 'rwops>,
     ) -> Self {
         Self {
             screen_area,
-            font_renderer: text_renderer,
+            font_renderer,
+            text_renderer,
         }
     }
 }
@@ -53,6 +64,25 @@ impl<'ttf_module, 'rwops> BoardRenderer<'ttf_module, 'rwops> {
             None
         };
         let active_piece = active_pos.map(|x| state.piece_at(*x)).flatten();
+        let dropping = if let Some(Interaction::StartDroppingPiece(piece)) = state.interaction() {
+            Some(*piece)
+        } else {
+            None
+        };
+        let drop_targets = match dropping {
+            Some(piece) => state
+                .legal_moves()
+                .into_iter()
+                .filter(|(_, m)| matches!(m, Move::Drop(kind, _) if *kind == piece.kind))
+                .map(|(to, _)| to)
+                .collect(),
+            None => vec![],
+        };
+        // the blown up squares and how opaque they are still
+        let explosion = state.explosion().and_then(|(squares, at)| {
+            let left = 1.0 - at.elapsed().as_secs_f32() / EXPLOSION_DURATION.as_secs_f32();
+            (left > 0.0).then_some((squares, (left * 200.0) as u8))
+        });
 
         // draw board
         for x in 0..BOARD_SIZE {
@@ -72,9 +102,20 @@ impl<'ttf_module, 'rwops> BoardRenderer<'ttf_module, 'rwops> {
 
                 canvas.fill_rect(rect)?;
 
+                if let Some((squares, alpha)) = explosion {
+                    if squares & square_bit(Position(x, y).idx()) != 0 {
+                        let blend_mode = canvas.blend_mode();
+                        canvas.set_blend_mode(BlendMode::Blend);
+                        let Color { r, g, b, .. } = EXPLOSION_COLOR;
+                        canvas.set_draw_color(Color::RGBA(r, g, b, alpha));
+                        canvas.fill_rect(rect)?;
+                        canvas.set_blend_mode(blend_mode);
+                    }
+                }
+
                 if let Some(active_pos) = active_pos {
                     if let Some(active_piece) = active_piece {
-                        for valid_move in active_piece.moves(*active_pos, state) {
+                        for valid_move in state.legal_moves_from(*active_pos) {
                             match valid_move {
                                 Move::Move(to) => {
                                     Self::highlight_if_position_match(canvas, to, x, y, rect)?;
@@ -86,18 +127,23 @@ impl<'ttf_module, 'rwops> BoardRenderer<'ttf_module, 'rwops> {
                                     Self::highlight_if_position_match(canvas, to, x, y, rect)?;
                                 }
                                 Move::Castle(side) => {
-                                    Self::highlight_if_position_match(
-                                        canvas,
-                                        side.positions(active_piece.color).king_end,
-                                        x,
-                                        y,
-                                        rect,
-                                    )?;
+                                    // the king can also be dropped onto the rook
+                                    let castling =
+                                        state.castling_movement(active_piece.color, side);
+                                    for target in [castling.king_end, castling.rook_start] {
+                                        Self::highlight_if_position_match(
+                                            canvas, target, x, y, rect,
+                                        )?;
+                                    }
                                 }
+                                Move::Drop(..) => {}
                             }
                         }
                     }
                 }
+                for target in &drop_targets {
+                    Self::highlight_if_position_match(canvas, *target, x, y, rect)?;
+                }
 
                 if let Some(position) = hovering {
                     Self::highlight_if_position_match(canvas, position, x, y, rect)?;
@@ -111,9 +157,80 @@ impl<'ttf_module, 'rwops> BoardRenderer<'ttf_module, 'rwops> {
             }
         }
 
+        if state.variant().has_drops() {
+            self.render_pockets(canvas, mouse_position, state, dropping)?;
+        }
+
+        Ok(())
+    }
+
+    /// Draws the pieces each side may drop beside the board, the pocket of black at the top.
+    fn render_pockets(
+        &self,
+        canvas: &mut WindowCanvas,
+        mouse_position: (i32, i32),
+        state: &GameState,
+        dropping: Option<Piece>,
+    ) -> Result<(), String> {
+        let hovering = self.pocket_piece_at(mouse_position.0, mouse_position.1);
+        for color in [PlayerColor::Black, PlayerColor::White] {
+            for kind in POCKET_KINDS {
+                let piece = Piece { kind, color };
+                let rect = self.pocket_rect(piece);
+                let count = state.pocket(color, kind);
+                if count == 0 {
+                    continue;
+                }
+                if dropping == Some(piece) || hovering == Some(piece) {
+                    let blend_mode = canvas.blend_mode();
+                    canvas.set_blend_mode(BlendMode::Mul);
+                    canvas.set_draw_color(SELECTED_HIGHLIGHT_COLOR);
+                    canvas.fill_rect(rect)?;
+                    canvas.set_blend_mode(blend_mode);
+                }
+                self.font_renderer
+                    .render_at(kind.to_ttf(color), rect, canvas)?;
+                let label = Rect::new(rect.right() + 4, rect.y(), rect.width(), rect.height());
+                self.text_renderer
+                    .render_at(&count.to_string(), label, canvas)?;
+            }
+        }
         Ok(())
     }
 
+    /// Where `piece` is shown in its pocket, in the margin left of the board.
+    fn pocket_rect(&self, piece: Piece) -> Rect {
+        let screen_small = min(self.screen_area.width(), self.screen_area.height()) as i32;
+        let cell_width = screen_small / BOARD_SIZE;
+        let offset_x = (self.screen_area.width() as i32 - screen_small) / 2;
+        let size = cell_width / 2;
+
+        let row = POCKET_KINDS
+            .iter()
+            .position(|kind| *kind == piece.kind)
+            .unwrap_or_default() as i32;
+        let top = match piece.color {
+            PlayerColor::Black => self.screen_area.y() + size,
+            PlayerColor::White => {
+                self.screen_area.bottom() - size * (POCKET_KINDS.len() as i32 + 1)
+            }
+        };
+        Rect::new(
+            self.screen_area.x() + offset_x / 2 - size,
+            top + row * size,
+            size as u32,
+            size as u32,
+        )
+    }
+
+    /// The pocket piece under the mouse, whether or not any are left.
+    pub fn pocket_piece_at(&self, mouse_x: i32, mouse_y: i32) -> Option<Piece> {
+        [PlayerColor::Black, PlayerColor::White]
+            .into_iter()
+            .flat_map(|color| POCKET_KINDS.map(|kind| Piece { kind, color }))
+            .find(|piece| self.pocket_rect(*piece).contains_point((mouse_x, mouse_y)))
+    }
+
     fn highlight_if_position_match(
         canvas: &mut WindowCanvas,
         Position(s_x, s_y): Position,
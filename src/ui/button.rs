@@ -0,0 +1,56 @@
+use crate::ui::board::SELECTED_HIGHLIGHT_COLOR;
+use crate::ui::text::FontRenderer;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, WindowCanvas};
+
+pub struct ButtonRenderer<'ttf_module, 'rwops> {
+    font_renderer: &'ttf_module FontRenderer<'ttf_module, 'rwops>,
+    render_area: Rect,
+    label: &'static str,
+}
+
+impl<'ttf_module, 'rwops> ButtonRenderer<'ttf_module, 'rwops> {
+    pub fn new(
+        font_renderer: &'ttf_module FontRenderer<'ttf_module, 'rwops>,
+        render_area: Rect,
+        label: &'static str,
+    ) -> Self {
+        Self {
+            font_renderer,
+            render_area,
+            label,
+        }
+    }
+}
+
+impl ButtonRenderer<'_, '_> {
+    pub fn render(
+        &self,
+        canvas: &mut WindowCanvas,
+        (mouse_x, mouse_y): (i32, i32),
+    ) -> Result<(), String> {
+        if self.mouse_is_over(mouse_x, mouse_y) {
+            let mode = canvas.blend_mode();
+            canvas.set_blend_mode(BlendMode::Mul);
+            canvas.set_draw_color(SELECTED_HIGHLIGHT_COLOR);
+            canvas.fill_rect(self.render_area)?;
+            canvas.set_blend_mode(mode);
+        }
+
+        canvas.set_draw_color(Color::BLACK);
+        canvas.draw_rect(self.render_area)?;
+
+        let text_area = Rect::new(
+            self.render_area.x + 4,
+            self.render_area.y + 2,
+            self.render_area.width() - 8,
+            self.render_area.height() - 4,
+        );
+        self.font_renderer.render_at(self.label, text_area, canvas)
+    }
+
+    pub fn mouse_is_over(&self, mouse_x: i32, mouse_y: i32) -> bool {
+        self.render_area.contains_point((mouse_x, mouse_y))
+    }
+}
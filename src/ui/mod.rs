@@ -1,3 +1,4 @@
 pub mod board;
+pub mod button;
 pub mod promotion;
 pub mod text;